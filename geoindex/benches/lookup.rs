@@ -1,6 +1,6 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion, ParameterizedBenchmark};
 use geo::{polygon, Point, Polygon};
-use geoindex::{GeoIndex, IndexDefinition};
+use geoindex::GeoIndex;
 
 fn triangle(scale: f32, tx: f32, ty: f32) -> Polygon<f32> {
     // base bounding rect: 0-6
@@ -60,13 +60,13 @@ fn prepare_dataset(cols: usize, rows: usize) -> GeoIndex<usize, f32> {
                 )
             })
         })
-        .collect::<IndexDefinition<_, _>>();
+        .collect::<Vec<_>>();
 
     GeoIndex::new(defs, 0)
 }
 
 fn lookup(needle: &Point<f32>, haystack: &GeoIndex<usize, f32>) -> usize {
-    *haystack.lookup_coords(needle)
+    *haystack.lookup_coords(Some(needle))
 }
 
 fn bench_lookup(c: &mut Criterion) {
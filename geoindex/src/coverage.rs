@@ -0,0 +1,465 @@
+use geo::{
+    algorithm::{bounding_rect::BoundingRect, contains::Contains},
+    Coordinate, Line, LineString, MultiPolygon, Point, Polygon,
+};
+
+use hashbrown::HashMap;
+
+use std::cmp::Ordering;
+
+use crate::ty::IndexCoordinate;
+
+// polygon of the region in the list of polygons passed to `uncovered`
+const REGION: usize = 0;
+
+fn cmp<V: IndexCoordinate>(a: &V, b: &V) -> Ordering {
+    a.partial_cmp(b).unwrap_or(Ordering::Equal)
+}
+
+fn cmp_coordinates<V: IndexCoordinate>(a: &Coordinate<V>, b: &Coordinate<V>) -> Ordering {
+    cmp(&a.x, &b.x).then_with(|| cmp(&a.y, &b.y))
+}
+
+/// Non-vertical polygon edge, running from left to right
+#[derive(Debug)]
+struct Edge<V: IndexCoordinate> {
+    line: Line<V>,
+    polygon: usize,
+}
+
+impl<V: IndexCoordinate> Edge<V> {
+    fn new(line: Line<V>, polygon: usize) -> Option<Self> {
+        match cmp(&line.start.x, &line.end.x) {
+            Ordering::Less => Some(Self { line, polygon }),
+            Ordering::Greater => Some(Self {
+                line: Line::new(line.end, line.start),
+                polygon,
+            }),
+            Ordering::Equal => None,
+        }
+    }
+
+    /// The y coordinate at `x`, exact at the ends of the edge,
+    /// so edges of adjacent slabs meet at the same points
+    fn y_at(&self, x: V) -> V {
+        if x == self.line.start.x {
+            self.line.start.y
+        } else if x == self.line.end.x {
+            self.line.end.y
+        } else {
+            self.line.start.y + (x - self.line.start.x) * self.line.dy() / self.line.dx()
+        }
+    }
+
+    /// The x coordinate of the crossing with the other edge, if they cross between their ends
+    fn crossing(&self, other: &Self) -> Option<V> {
+        let (a, b) = (&self.line, &other.line);
+        let denominator = a.dx() * b.dy() - a.dy() * b.dx();
+
+        if denominator == V::zero() {
+            return None;
+        }
+
+        let offset_x = b.start.x - a.start.x;
+        let offset_y = b.start.y - a.start.y;
+        let t = (offset_x * b.dy() - offset_y * b.dx()) / denominator;
+        let s = (offset_x * a.dy() - offset_y * a.dx()) / denominator;
+
+        if t > V::zero() && t < V::one() && s > V::zero() && s < V::one() {
+            Some(a.start.x + a.dx() * t)
+        } else {
+            None
+        }
+    }
+}
+
+/// Directed piece of the boundary of the uncovered region,
+/// along an edge of an input polygon or vertical along a slab border
+#[derive(Debug, Clone, Copy)]
+struct Segment<V: IndexCoordinate> {
+    start: Coordinate<V>,
+    end: Coordinate<V>,
+    edge: Option<usize>,
+}
+
+/// x coordinates of every vertex and edge crossing, splitting the plane into slabs
+/// where edges don't cross and the coverage only changes at the edges
+fn slab_borders<V: IndexCoordinate>(edges: &[Edge<V>], min: V, max: V) -> Vec<V> {
+    let mut borders = edges
+        .iter()
+        .flat_map(|edge| vec![edge.line.start.x, edge.line.end.x])
+        .collect::<Vec<_>>();
+
+    // edges overlapping on the x axis might cross
+    let mut order = (0..edges.len()).collect::<Vec<_>>();
+    order.sort_by(|a, b| cmp(&edges[*a].line.start.x, &edges[*b].line.start.x));
+
+    for (position, &a) in order.iter().enumerate() {
+        borders.extend(
+            order[position + 1..]
+                .iter()
+                .take_while(|&&b| edges[b].line.start.x < edges[a].line.end.x)
+                .filter_map(|&b| edges[a].crossing(&edges[b])),
+        );
+    }
+
+    borders.push(min);
+    borders.push(max);
+    borders.retain(|x| *x >= min && *x <= max);
+    borders.sort_by(cmp);
+    borders.dedup();
+    borders
+}
+
+/// Boundary of the parts of the region not covered by any other polygon,
+/// as trapezoids with the slab borders between them cancelled out, along with their area
+fn uncovered_boundary<V: IndexCoordinate>(polygons: &[&Polygon<V>]) -> (Vec<Segment<V>>, V) {
+    let edges = polygons
+        .iter()
+        .enumerate()
+        .flat_map(|(index, polygon)| {
+            Some(polygon.exterior())
+                .into_iter()
+                .chain(polygon.interiors())
+                .flat_map(LineString::lines)
+                .filter_map(move |line| Edge::new(line, index))
+        })
+        .collect::<Vec<_>>();
+
+    let bounds = match polygons[REGION].bounding_rect() {
+        Some(bounds) => bounds,
+        None => return (Vec::new(), V::zero()),
+    };
+    let borders = slab_borders(&edges, bounds.min.x, bounds.max.x);
+    let two = V::one() + V::one();
+
+    let mut segments = Vec::new();
+    // vertical sides of the trapezoids, pointing up on their right side
+    let mut verticals: Vec<(V, V, V, bool)> = Vec::new();
+    let mut area = V::zero();
+
+    // edges spanning the current slab, every edge spans whole slabs as they end at the borders
+    let mut pending = (0..edges.len()).collect::<Vec<_>>();
+    pending.sort_by(|a, b| cmp(&edges[*b].line.start.x, &edges[*a].line.start.x));
+    let mut active = Vec::new();
+
+    for slab in borders.windows(2) {
+        let (left, right) = (slab[0], slab[1]);
+        let middle = (left + right) / two;
+
+        while let Some(&next) = pending.last() {
+            if edges[next].line.start.x > left {
+                break;
+            }
+
+            active.push(next);
+            pending.pop();
+        }
+        active.retain(|index| edges[*index].line.end.x >= right);
+
+        let mut crossing = active
+            .iter()
+            .map(|&index| (edges[index].y_at(middle), index))
+            .collect::<Vec<_>>();
+        crossing.sort_by(|a, b| cmp(&a.0, &b.0));
+
+        let mut inside = vec![false; polygons.len()];
+        let mut bottom = None;
+        let mut position = 0;
+
+        while position < crossing.len() {
+            // edges meeting at the middle of the slab change the coverage together
+            let y = crossing[position].0;
+            let first = crossing[position].1;
+
+            while position < crossing.len() && crossing[position].0 == y {
+                let polygon = edges[crossing[position].1].polygon;
+                inside[polygon] = !inside[polygon];
+                position += 1;
+            }
+
+            let last = crossing[position - 1].1;
+            let uncovered = inside[REGION] && !inside[REGION + 1..].iter().any(|inside| *inside);
+
+            match (bottom, uncovered) {
+                (None, true) => bottom = Some(last),
+                (Some(bottom_edge), false) => {
+                    bottom = None;
+
+                    let (bottom_line, top_line) = (&edges[bottom_edge], &edges[first]);
+                    let bottom_left = Coordinate {
+                        x: left,
+                        y: bottom_line.y_at(left),
+                    };
+                    let bottom_right = Coordinate {
+                        x: right,
+                        y: bottom_line.y_at(right),
+                    };
+                    let top_left = Coordinate {
+                        x: left,
+                        y: top_line.y_at(left),
+                    };
+                    let top_right = Coordinate {
+                        x: right,
+                        y: top_line.y_at(right),
+                    };
+
+                    area = area
+                        + ((top_left.y - bottom_left.y) + (top_right.y - bottom_right.y))
+                            * (right - left)
+                            / two;
+
+                    segments.push(Segment {
+                        start: bottom_left,
+                        end: bottom_right,
+                        edge: Some(bottom_edge),
+                    });
+                    segments.push(Segment {
+                        start: top_right,
+                        end: top_left,
+                        edge: Some(first),
+                    });
+                    verticals.push((right, bottom_right.y, top_right.y, true));
+                    verticals.push((left, bottom_left.y, top_left.y, false));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    segments.extend(cancel_verticals(verticals));
+
+    (segments, area)
+}
+
+/// Vertical segments left after the ones shared by adjacent trapezoids cancel out
+fn cancel_verticals<V: IndexCoordinate>(verticals: Vec<(V, V, V, bool)>) -> Vec<Segment<V>> {
+    // edges crossing at a slab border might swap their order by a rounding error there
+    let mut verticals = verticals
+        .into_iter()
+        .map(|(x, bottom, top, up)| {
+            if bottom > top {
+                (x, top, bottom, !up)
+            } else {
+                (x, bottom, top, up)
+            }
+        })
+        .collect::<Vec<_>>();
+    verticals.sort_by(|a, b| cmp(&a.0, &b.0));
+
+    let mut segments = Vec::new();
+
+    for border in verticals.chunk_by(|a, b| a.0 == b.0) {
+        let x = border[0].0;
+        let mut breaks = border
+            .iter()
+            .flat_map(|&(_, bottom, top, _)| vec![bottom, top])
+            .collect::<Vec<_>>();
+        breaks.sort_by(cmp);
+        breaks.dedup();
+
+        // net direction of every piece between the breaks, +1 for up
+        let pieces = breaks.windows(2).map(|piece| {
+            let direction = border
+                .iter()
+                .filter(|&&(_, bottom, top, _)| bottom <= piece[0] && top >= piece[1])
+                .map(|&(_, _, _, up)| if up { 1 } else { -1 })
+                .sum::<i32>();
+
+            (piece[0], piece[1], direction)
+        });
+
+        let mut run: Option<(V, V, i32)> = None;
+
+        for (bottom, top, direction) in pieces.chain(Some((V::zero(), V::zero(), 0))) {
+            run = match run {
+                Some((run_bottom, run_top, run_direction))
+                    if run_direction == direction && run_top == bottom =>
+                {
+                    Some((run_bottom, top, direction))
+                }
+                previous => {
+                    if let Some((run_bottom, run_top, run_direction)) = previous {
+                        let (lower, upper) = (
+                            Coordinate { x, y: run_bottom },
+                            Coordinate { x, y: run_top },
+                        );
+
+                        match run_direction {
+                            1 => segments.push(Segment {
+                                start: lower,
+                                end: upper,
+                                edge: None,
+                            }),
+                            -1 => segments.push(Segment {
+                                start: upper,
+                                end: lower,
+                                edge: None,
+                            }),
+                            _ => {}
+                        }
+                    }
+
+                    Some((bottom, top, direction))
+                }
+            };
+        }
+    }
+
+    segments
+}
+
+/// Chain the segments into closed rings, merging consecutive segments along the same line
+fn rings<V: IndexCoordinate>(segments: &[Segment<V>]) -> Vec<LineString<V>> {
+    let mut vertices = segments
+        .iter()
+        .map(|segment| segment.start)
+        .collect::<Vec<_>>();
+    vertices.sort_by(cmp_coordinates);
+    vertices.dedup();
+
+    let vertex = |coordinate: &Coordinate<V>| {
+        vertices
+            .binary_search_by(|vertex| cmp_coordinates(vertex, coordinate))
+            .ok()
+    };
+
+    let mut outgoing = vec![Vec::new(); vertices.len()];
+    for (index, segment) in segments.iter().enumerate() {
+        if let Some(start) = vertex(&segment.start) {
+            outgoing[start].push(index);
+        }
+    }
+
+    let mut rings = Vec::new();
+
+    for start in 0..vertices.len() {
+        while let Some(first) = outgoing[start].pop() {
+            let mut chain = vec![first];
+
+            while let Some(next) = vertex(&segments[chain[chain.len() - 1]].end)
+                .filter(|end| *end != start)
+                .and_then(|end| outgoing[end].pop())
+            {
+                chain.push(next);
+            }
+
+            // rings touching at a vertex might be chained together, split them there
+            let mut pending: Vec<usize> = Vec::new();
+            let mut positions = HashMap::new();
+
+            for segment in chain {
+                if let Some(start) = vertex(&segments[segment].start) {
+                    if let Some(&loop_start) = positions.get(&start) {
+                        let ring = pending.split_off(loop_start);
+                        positions.retain(|_, position| *position < loop_start);
+                        rings.push(merge_collinear(ring.iter().map(|s| segments[*s])));
+                    }
+
+                    positions.insert(start, pending.len());
+                }
+
+                pending.push(segment);
+            }
+
+            rings.push(merge_collinear(pending.iter().map(|s| segments[*s])));
+        }
+    }
+
+    rings
+}
+
+fn same_line<V: IndexCoordinate>(a: &Segment<V>, b: &Segment<V>) -> bool {
+    match (a.edge, b.edge) {
+        (Some(a), Some(b)) => a == b,
+        (None, None) => a.start.x == b.start.x,
+        _ => false,
+    }
+}
+
+fn merge_collinear<V: IndexCoordinate>(chain: impl Iterator<Item = Segment<V>>) -> LineString<V> {
+    let mut merged: Vec<Segment<V>> = Vec::new();
+
+    for segment in chain {
+        match merged.last_mut() {
+            Some(last) if same_line(last, &segment) => last.end = segment.end,
+            _ => merged.push(segment),
+        }
+    }
+
+    if merged.len() > 1 && same_line(&merged[0], &merged[merged.len() - 1]) {
+        let last = merged.pop().unwrap();
+        merged[0].start = last.start;
+    }
+
+    let mut points = merged
+        .iter()
+        .map(|segment| segment.start)
+        .collect::<Vec<_>>();
+    points.extend(points.first().cloned());
+
+    points.into()
+}
+
+/// Shoelace formula, positive for counter-clockwise rings
+fn signed_area<V: IndexCoordinate>(ring: &LineString<V>) -> V {
+    ring.lines().fold(V::zero(), |area, line| {
+        area + line.start.x * line.end.y - line.end.x * line.start.y
+    }) / (V::one() + V::one())
+}
+
+/// Area of the polygon without its holes, whatever the orientation of its rings
+pub(crate) fn polygon_area<V: IndexCoordinate>(polygon: &Polygon<V>) -> V {
+    polygon
+        .interiors()
+        .iter()
+        .fold(signed_area(polygon.exterior()).abs(), |area, hole| {
+            area - signed_area(hole).abs()
+        })
+}
+
+/// Parts of the region not covered by any of the polygons, along with their area
+pub(crate) fn uncovered<V: IndexCoordinate>(
+    region: &Polygon<V>,
+    polygons: &[&Polygon<V>],
+) -> (MultiPolygon<V>, V) {
+    let all = Some(region)
+        .into_iter()
+        .chain(polygons.iter().cloned())
+        .collect::<Vec<_>>();
+    let (segments, area) = uncovered_boundary(&all);
+
+    // trapezoids are counter-clockwise, so are the outer rings of the uncovered parts,
+    // holes within them are clockwise
+    let (mut exteriors, holes): (Vec<_>, Vec<_>) = rings(&segments)
+        .into_iter()
+        .map(|ring| (signed_area(&ring), ring))
+        .filter(|(area, _)| *area != V::zero())
+        .partition(|(area, _)| *area > V::zero());
+    exteriors.sort_by(|a, b| cmp(&a.0, &b.0));
+
+    let mut parts = exteriors
+        .into_iter()
+        .map(|(_, exterior)| (Polygon::new(exterior, Vec::new()), Vec::new()))
+        .collect::<Vec<_>>();
+
+    for (_, hole) in holes {
+        // the smallest part containing the hole, holes might touch the outer ring
+        let part = parts.iter_mut().find(|(exterior, _)| {
+            hole.0
+                .iter()
+                .any(|coordinate| exterior.contains(&Point(*coordinate)))
+        });
+
+        if let Some((_, part_holes)) = part {
+            part_holes.push(hole);
+        }
+    }
+
+    let parts = parts
+        .into_iter()
+        .map(|(exterior, holes)| Polygon::new(exterior.exterior().clone(), holes))
+        .collect::<Vec<_>>();
+
+    (MultiPolygon(parts), area)
+}
@@ -1,16 +1,27 @@
 use geo::{
-    algorithm::{bounding_rect::BoundingRect, contains::Contains},
+    algorithm::{
+        bounding_rect::BoundingRect, contains::Contains, euclidean_distance::EuclideanDistance,
+    },
     Point, Polygon,
 };
-use rstar::{self, PointDistance, RTreeObject, AABB};
+use rstar::{self, Envelope, PointDistance, RTreeObject, AABB};
 
-use crate::ty::IndexCoordinate;
+use std::cmp::{Ordering, Reverse};
+
+use crate::coverage::polygon_area;
+use crate::ty::{IndexCoordinate, OverlapStrategy};
 
 #[derive(Debug, PartialEq)]
 pub(crate) struct IndexEntry<V: IndexCoordinate = f32> {
     envelope: AABB<[V; 2]>,
     polygon: Polygon<V>,
+    area: V,
     value_index: usize,
+    // position of the polygon among the polygons of the value
+    polygon_index: usize,
+    // position of the polygon in the index definition
+    order: usize,
+    priority: i32,
 }
 
 impl<V> RTreeObject for IndexEntry<V>
@@ -28,13 +39,20 @@ where
 impl<V> PointDistance for IndexEntry<V>
 where
     V: IndexCoordinate,
-    [V; 2]: rstar::Point,
+    [V; 2]: rstar::Point<Scalar = V>,
 {
     fn distance_2(
         &self,
         point: &<Self::Envelope as rstar::Envelope>::Point,
     ) -> <<Self::Envelope as rstar::Envelope>::Point as rstar::Point>::Scalar {
-        self.envelope.distance_2(point)
+        let distance = self.distance(&Point::new(point[0], point[1]));
+
+        distance * distance
+    }
+
+    fn contains_point(&self, point: &<Self::Envelope as rstar::Envelope>::Point) -> bool {
+        // exact containment is checked separately, see `IndexEntry::contains`
+        self.envelope.contains_point(point)
     }
 }
 
@@ -43,6 +61,45 @@ where
     V: IndexCoordinate,
     [V; 2]: rstar::Point,
 {
+    pub fn new(
+        polygon: &Polygon<V>,
+        value_index: usize,
+        polygon_index: usize,
+        order: usize,
+        priority: i32,
+    ) -> Self {
+        let envelope = Self::envelope_from_polygon(polygon);
+
+        Self {
+            envelope,
+            polygon: polygon.clone(),
+            area: polygon_area(polygon),
+            value_index,
+            polygon_index,
+            order,
+            priority,
+        }
+    }
+
+    /// Entry of a part of the polygon, keeping the area of the whole polygon
+    pub fn part(&self, polygon: Polygon<V>) -> Self {
+        Self {
+            envelope: Self::envelope_from_polygon(&polygon),
+            polygon,
+            ..*self
+        }
+    }
+
+    /// Entry with the envelope stretched to the latitudes, e.g. those reached
+    /// by great circle edges between the vertices
+    pub fn with_latitudes(mut self, min: V, max: V) -> Self {
+        let (lower, upper) = (self.envelope.lower(), self.envelope.upper());
+
+        self.envelope =
+            AABB::from_corners([lower[0], lower[1].min(min)], [upper[0], upper[1].max(max)]);
+        self
+    }
+
     fn envelope_from_polygon(poly: &Polygon<V>) -> AABB<[V; 2]> {
         let bb = poly
             .bounding_rect()
@@ -61,23 +118,44 @@ where
         self.value_index
     }
 
+    pub fn polygon_index(&self) -> usize {
+        self.polygon_index
+    }
+
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    pub fn polygon(&self) -> &Polygon<V> {
+        &self.polygon
+    }
+
     pub fn contains(&self, point: &Point<V>) -> bool {
         self.polygon.contains(point)
     }
-}
 
-impl<'a, V> From<(&'a Polygon<V>, usize)> for IndexEntry<V>
-where
-    V: IndexCoordinate,
-    [V; 2]: rstar::Point,
-{
-    fn from((polygon, value_index): (&'a Polygon<V>, usize)) -> Self {
-        let envelope = Self::envelope_from_polygon(polygon);
+    /// Distance between the point and the polygon, zero if the point lies within
+    pub fn distance(&self, point: &Point<V>) -> V {
+        point.euclidean_distance(&self.polygon)
+    }
 
-        Self {
-            envelope,
-            polygon: polygon.clone(),
-            value_index,
+    /// Order entries by preference, according to the given strategy
+    pub fn cmp_by(&self, other: &Self, strategy: OverlapStrategy) -> Ordering {
+        // areas are never NaN for valid polygons
+        let by_area = || {
+            self.area
+                .partial_cmp(&other.area)
+                .unwrap_or(Ordering::Equal)
+        };
+        let by_priority = || Reverse(self.priority).cmp(&Reverse(other.priority));
+        let by_order = || self.order.cmp(&other.order);
+
+        match strategy {
+            OverlapStrategy::FirstDeclared => by_order(),
+            OverlapStrategy::HighestPriority => {
+                by_priority().then_with(by_area).then_with(by_order)
+            }
+            OverlapStrategy::SmallestArea => by_area().then_with(by_priority).then_with(by_order),
         }
     }
 }
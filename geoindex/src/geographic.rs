@@ -0,0 +1,340 @@
+use geo::{Coordinate, LineString, Point, Polygon, Rect};
+
+use crate::ty::IndexCoordinate;
+
+/// Mean radius of the Earth, in meters
+pub(crate) const EARTH_RADIUS: f64 = 6_371_008.8;
+
+pub(crate) fn constant<V: IndexCoordinate>(value: f64) -> V {
+    V::from(value).unwrap()
+}
+
+/// Whether the coordinate is a longitude and latitude within their ranges
+pub(crate) fn in_range<V: IndexCoordinate>(coordinate: Coordinate<V>) -> bool {
+    let (half_turn, quarter_turn) = (constant::<V>(180.), constant::<V>(90.));
+
+    coordinate.x >= -half_turn
+        && coordinate.x <= half_turn
+        && coordinate.y >= -quarter_turn
+        && coordinate.y <= quarter_turn
+}
+
+/// Coordinates of the ring with continuous longitudes,
+/// edges spanning more than half a turn are taken to cross the antimeridian
+fn unwrap_ring<V: IndexCoordinate>(ring: &LineString<V>) -> Vec<Coordinate<V>> {
+    let (half_turn, turn) = (constant::<V>(180.), constant::<V>(360.));
+    let mut offset = V::zero();
+
+    ring.0
+        .iter()
+        .enumerate()
+        .map(|(index, coordinate)| {
+            if index > 0 {
+                let step = coordinate.x - ring.0[index - 1].x;
+
+                if step > half_turn {
+                    offset = offset - turn;
+                } else if step < -half_turn {
+                    offset = offset + turn;
+                }
+            }
+
+            Coordinate {
+                x: coordinate.x + offset,
+                y: coordinate.y,
+            }
+        })
+        .collect()
+}
+
+/// Whether going around the ring adds up to a full turn of longitude, i.e. the ring encircles a pole
+pub(crate) fn encircles_pole<V: IndexCoordinate>(ring: &LineString<V>) -> bool {
+    let unwrapped = unwrap_ring(ring);
+
+    match (unwrapped.first(), unwrapped.last()) {
+        (Some(first), Some(last)) => first.x != last.x,
+        _ => false,
+    }
+}
+
+/// Part of the ring between the `min` and `max` longitudes, Sutherland-Hodgman style
+fn clip<V: IndexCoordinate>(ring: &[Coordinate<V>], min: V, max: V) -> Vec<Coordinate<V>> {
+    let clip_side = |ring: Vec<Coordinate<V>>, bound: V, inside: &dyn Fn(V) -> bool| {
+        let mut clipped = Vec::new();
+        let crossing = |start: Coordinate<V>, end: Coordinate<V>| Coordinate {
+            x: bound,
+            y: start.y + (bound - start.x) * (end.y - start.y) / (end.x - start.x),
+        };
+
+        for (index, &end) in ring.iter().enumerate() {
+            let start = ring[(index + ring.len() - 1) % ring.len()];
+
+            match (inside(start.x), inside(end.x)) {
+                (true, true) => clipped.push(end),
+                (false, true) => {
+                    clipped.push(crossing(start, end));
+                    clipped.push(end);
+                }
+                (true, false) => clipped.push(crossing(start, end)),
+                (false, false) => {}
+            }
+        }
+
+        clipped
+    };
+
+    let clipped = clip_side(ring.to_vec(), min, &|x| x >= min);
+    clip_side(clipped, max, &|x| x <= max)
+}
+
+/// Polygon split along the antimeridian into parts within the longitude range,
+/// polygons not crossing it are returned as they are
+pub(crate) fn split_antimeridian<V: IndexCoordinate>(polygon: &Polygon<V>) -> Vec<Polygon<V>> {
+    let (half_turn, turn) = (constant::<V>(180.), constant::<V>(360.));
+
+    let exterior = unwrap_ring(polygon.exterior());
+    let (min, max) = exterior.iter().fold(
+        (V::infinity(), V::neg_infinity()),
+        |(min, max), coordinate| (min.min(coordinate.x), max.max(coordinate.x)),
+    );
+
+    if exterior.is_empty() || (min >= -half_turn && max <= half_turn) {
+        return vec![polygon.clone()];
+    }
+
+    // holes are moved by whole turns next to the exterior
+    let middle = (min + max) / (V::one() + V::one());
+    let interiors = polygon
+        .interiors()
+        .iter()
+        .map(|interior| {
+            let mut interior = unwrap_ring(interior);
+            let shift = interior
+                .first()
+                .map(|first| ((middle - first.x) / turn).round() * turn)
+                .unwrap_or_else(V::zero);
+
+            interior
+                .iter_mut()
+                .for_each(|coordinate| coordinate.x = coordinate.x + shift);
+            interior
+        })
+        .collect::<Vec<_>>();
+
+    // each turn of unwrapped longitudes is clipped and moved back within the range
+    [-turn, V::zero(), turn]
+        .iter()
+        .filter(|&&shift| min < shift + half_turn && max > shift - half_turn)
+        .filter_map(|&shift| {
+            let ring = |coordinates: &[Coordinate<V>]| {
+                let clipped = clip(coordinates, shift - half_turn, shift + half_turn);
+
+                if clipped.len() < 3 {
+                    return None;
+                }
+
+                Some(LineString::from(
+                    clipped
+                        .into_iter()
+                        .map(|coordinate| Coordinate {
+                            x: coordinate.x - shift,
+                            y: coordinate.y,
+                        })
+                        .collect::<Vec<_>>(),
+                ))
+            };
+
+            Some(Polygon::new(
+                ring(&exterior)?,
+                interiors
+                    .iter()
+                    .filter_map(|interior| ring(interior))
+                    .collect(),
+            ))
+        })
+        .collect()
+}
+
+fn radians<V: IndexCoordinate>(point: Coordinate<V>) -> (V, V) {
+    (point.x.to_radians(), point.y.to_radians())
+}
+
+/// Central angle between the points, with the haversine formula
+fn central_angle<V: IndexCoordinate>(a: Coordinate<V>, b: Coordinate<V>) -> V {
+    let ((lon1, lat1), (lon2, lat2)) = (radians(a), radians(b));
+    let two = V::one() + V::one();
+
+    let h = ((lat2 - lat1) / two).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / two).sin().powi(2);
+
+    two * h.sqrt().min(V::one()).asin()
+}
+
+/// Initial bearing of the great circle from `a` to `b`
+fn bearing<V: IndexCoordinate>(a: Coordinate<V>, b: Coordinate<V>) -> V {
+    let ((lon1, lat1), (lon2, lat2)) = (radians(a), radians(b));
+    let delta = lon2 - lon1;
+
+    (delta.sin() * lat2.cos())
+        .atan2(lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * delta.cos())
+}
+
+/// Central angle between the point and the great circle arc from `start` to `end`
+fn angle_to_arc<V: IndexCoordinate>(
+    point: Coordinate<V>,
+    start: Coordinate<V>,
+    end: Coordinate<V>,
+) -> V {
+    let to_start = central_angle(start, point);
+    let length = central_angle(start, end);
+
+    if to_start == V::zero() || length == V::zero() {
+        return to_start;
+    }
+
+    let relative_bearing = bearing(start, point) - bearing(start, end);
+
+    // the point lies behind the start of the arc
+    if relative_bearing.cos() < V::zero() {
+        return to_start;
+    }
+
+    let cross_track = (to_start.sin() * relative_bearing.sin()).asin();
+    let along_track = (to_start.cos() / cross_track.cos())
+        .max(-V::one())
+        .min(V::one())
+        .acos();
+
+    if along_track > length {
+        central_angle(end, point)
+    } else {
+        cross_track.abs()
+    }
+}
+
+/// Great circle distance between the point and the boundary of the polygon, in meters
+pub(crate) fn distance<V: IndexCoordinate>(point: &Point<V>, polygon: &Polygon<V>) -> V {
+    let angle = Some(polygon.exterior())
+        .into_iter()
+        .chain(polygon.interiors())
+        .flat_map(LineString::lines)
+        .map(|line| angle_to_arc(point.0, line.start, line.end))
+        .fold(V::infinity(), V::min);
+
+    angle * constant(EARTH_RADIUS)
+}
+
+type Vector<V> = [V; 3];
+
+fn unit_vector<V: IndexCoordinate>(point: Coordinate<V>) -> Vector<V> {
+    let (lon, lat) = radians(point);
+
+    [lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin()]
+}
+
+fn cross<V: IndexCoordinate>(a: Vector<V>, b: Vector<V>) -> Vector<V> {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot<V: IndexCoordinate>(a: Vector<V>, b: Vector<V>) -> V {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// Lowest and highest latitudes of the great circle arc from `start` to `end`,
+/// arcs bulge towards the poles beyond the latitudes of their ends
+fn arc_latitudes<V: IndexCoordinate>(start: Coordinate<V>, end: Coordinate<V>) -> (V, V) {
+    let (a, b) = (unit_vector(start), unit_vector(end));
+    let normal = cross(a, b);
+    let mut range = (start.y.min(end.y), start.y.max(end.y));
+
+    // the northernmost point of the great circle, arcs along the equator have none
+    let top = [
+        -normal[0] * normal[2],
+        -normal[1] * normal[2],
+        normal[0] * normal[0] + normal[1] * normal[1],
+    ];
+    if top[2] == V::zero() {
+        return range;
+    }
+
+    let on_arc = |point: Vector<V>| {
+        dot(cross(a, point), normal) >= V::zero() && dot(cross(point, b), normal) >= V::zero()
+    };
+    let latitude = top[2]
+        .atan2((top[0] * top[0] + top[1] * top[1]).sqrt())
+        .to_degrees();
+
+    if on_arc(top) {
+        range.1 = range.1.max(latitude);
+    }
+    if on_arc([-top[0], -top[1], -top[2]]) {
+        range.0 = range.0.min(-latitude);
+    }
+
+    range
+}
+
+/// Lowest and highest latitudes of the polygon, with its edges following great circles
+pub(crate) fn latitude_range<V: IndexCoordinate>(polygon: &Polygon<V>) -> (V, V) {
+    polygon
+        .exterior()
+        .lines()
+        .map(|line| arc_latitudes(line.start, line.end))
+        .fold(
+            (V::infinity(), V::neg_infinity()),
+            |(min, max), (low, high)| (min.min(low), max.max(high)),
+        )
+}
+
+/// Boxes of longitudes and latitudes covering every point within the distance (in meters),
+/// split along the antimeridian
+///
+/// See Jan Philip Matuschek, "Finding Points Within a Distance of a Latitude/Longitude
+/// Using Bounding Coordinates".
+pub(crate) fn bounding_boxes<V: IndexCoordinate>(point: &Point<V>, distance: V) -> Vec<Rect<V>> {
+    let (half_turn, quarter_turn, turn) =
+        (constant::<V>(180.), constant::<V>(90.), constant::<V>(360.));
+    let angle = distance / constant(EARTH_RADIUS);
+    let (min_lat, max_lat) = (
+        point.y() - angle.to_degrees(),
+        point.y() + angle.to_degrees(),
+    );
+    let rect = |min_x, min_y, max_x, max_y| Rect {
+        min: Coordinate { x: min_x, y: min_y },
+        max: Coordinate { x: max_x, y: max_y },
+    };
+
+    // the distance reaches a pole, every longitude is within it
+    if min_lat <= -quarter_turn || max_lat >= quarter_turn {
+        return vec![rect(
+            -half_turn,
+            min_lat.max(-quarter_turn),
+            half_turn,
+            max_lat.min(quarter_turn),
+        )];
+    }
+
+    let delta = (angle.sin() / point.y().to_radians().cos())
+        .min(V::one())
+        .asin()
+        .to_degrees();
+    let (min_lon, max_lon) = (point.x() - delta, point.x() + delta);
+
+    if min_lon < -half_turn {
+        vec![
+            rect(min_lon + turn, min_lat, half_turn, max_lat),
+            rect(-half_turn, min_lat, max_lon, max_lat),
+        ]
+    } else if max_lon > half_turn {
+        vec![
+            rect(min_lon, min_lat, half_turn, max_lat),
+            rect(-half_turn, min_lat, max_lon - turn, max_lat),
+        ]
+    } else {
+        vec![rect(min_lon, min_lat, max_lon, max_lat)]
+    }
+}
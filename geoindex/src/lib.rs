@@ -1,12 +1,24 @@
-use geo::Point;
-use rstar::{self, RTree};
+use geo::{algorithm::bounding_rect::BoundingRect, MultiPolygon, Point, Polygon};
+use hashbrown::HashSet;
+use rstar::{self, RTree, RTreeObject, AABB};
 
 use std::fmt::Debug;
 
+use crate::coverage::{polygon_area, uncovered};
 use crate::entry::IndexEntry;
-use crate::ty::{IndexCoordinate, IndexDefinition};
+use crate::geographic::{latitude_range, split_antimeridian};
+use crate::nearest::GeographicNearest;
+use crate::overlap::intersection;
+pub use crate::ty::{
+    AreaDefinition, CoordinateSystem, Coverage, Fallback, IndexCoordinate, IndexDefinition,
+    LookupReason, Overlap, OverlapStrategy,
+};
 
+mod coverage;
 mod entry;
+mod geographic;
+mod nearest;
+mod overlap;
 mod ty;
 
 #[derive(Debug)]
@@ -14,17 +26,39 @@ pub struct GeoIndex<T: Debug, V: IndexCoordinate = f32> {
     index: RTree<IndexEntry<V>>,
     values: Vec<T>,
     default: T,
+    strategy: OverlapStrategy,
+    fallback: Fallback<V>,
+    system: CoordinateSystem,
 }
 
 impl<T: Debug, V: IndexCoordinate> GeoIndex<T, V> {
-    pub fn new(defs: IndexDefinition<T, V>, default: T) -> Self {
+    pub fn new<D>(defs: impl IntoIterator<Item = D>, default: T) -> Self
+    where
+        D: Into<AreaDefinition<T, V>>,
+    {
+        let defs = defs
+            .into_iter()
+            .map(Into::into)
+            .collect::<IndexDefinition<T, V>>();
+
         let index = defs
             .iter()
             .enumerate()
-            .flat_map(|(id, (polys, _))| polys.iter().map(move |poly| IndexEntry::from((poly, id))))
+            .flat_map(|(id, def)| {
+                let priority = def.priority.unwrap_or_default();
+
+                def.polygons
+                    .iter()
+                    .enumerate()
+                    .map(move |(polygon_index, poly)| (poly, id, polygon_index, priority))
+            })
+            .enumerate()
+            .map(|(order, (poly, id, polygon_index, priority))| {
+                IndexEntry::new(poly, id, polygon_index, order, priority)
+            })
             .collect();
 
-        let values = defs.into_iter().map(|(_, v)| v).collect();
+        let values = defs.into_iter().map(|def| def.value).collect();
 
         let index = RTree::bulk_load(index);
 
@@ -32,25 +66,286 @@ impl<T: Debug, V: IndexCoordinate> GeoIndex<T, V> {
             index,
             values,
             default,
+            strategy: OverlapStrategy::default(),
+            fallback: Fallback::default(),
+            system: CoordinateSystem::default(),
+        }
+    }
+
+    /// Set the strategy used to pick a value in case of overlapping polygons
+    pub fn with_strategy(mut self, strategy: OverlapStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Set the behaviour of lookups for points outside of every polygon
+    pub fn with_fallback(mut self, fallback: Fallback<V>) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Set the meaning of the coordinates, polygons crossing the antimeridian
+    /// are split along it in the geographic system, and their bounding boxes
+    /// include the latitudes reached by their edges along great circles
+    pub fn with_coordinate_system(mut self, system: CoordinateSystem) -> Self {
+        if system == CoordinateSystem::Geographic && self.system != system {
+            let entries = self
+                .index
+                .iter()
+                .flat_map(|entry| {
+                    split_antimeridian(entry.polygon())
+                        .into_iter()
+                        .map(move |part| {
+                            let (min, max) = latitude_range(&part);
+
+                            entry.part(part).with_latitudes(min, max)
+                        })
+                })
+                .collect();
+
+            self.index = RTree::bulk_load(entries);
         }
+
+        self.system = system;
+        self
+    }
+
+    pub fn coordinate_system(&self) -> CoordinateSystem {
+        self.system
+    }
+
+    fn matching_entries(&self, coords: Point<V>) -> impl Iterator<Item = &IndexEntry<V>> {
+        self.index
+            .locate_all_at_point(&[coords.x(), coords.y()])
+            .filter(move |entry| entry.contains(&coords))
     }
 
     pub fn lookup_coords(&self, coords: Option<&Point<V>>) -> &T {
-        coords
+        self.lookup_coords_by(coords, |_| true)
+    }
+
+    /// Lookup skipping values rejected by the filter,
+    /// falls through to the next matching polygon and eventually to the default value
+    pub fn lookup_coords_by(&self, coords: Option<&Point<V>>, filter: impl Fn(&T) -> bool) -> &T {
+        self.explain_by(coords, filter).0
+    }
+
+    pub fn explain(&self, coords: Option<&Point<V>>) -> (&T, LookupReason<V>) {
+        self.explain_by(coords, |_| true)
+    }
+
+    /// Same as `lookup_coords_by`, along with the reason the value was selected
+    pub fn explain_by(
+        &self,
+        coords: Option<&Point<V>>,
+        filter: impl Fn(&T) -> bool,
+    ) -> (&T, LookupReason<V>) {
+        let coords = match coords {
+            Some(coords) => coords,
+            None => return (&self.default, LookupReason::NoLocation),
+        };
+
+        let mut skipped = 0;
+
+        for value in self.lookup_all(coords) {
+            if filter(value) {
+                return (value, LookupReason::Area { skipped });
+            }
+
+            skipped += 1;
+        }
+
+        let nearest = match self.fallback {
+            Fallback::Default => None,
+            Fallback::Nearest { max_distance } => self
+                .nearest_entries(coords, max_distance)
+                .map(|(entry, distance)| (&self.values[entry.value_index()], distance))
+                .find(|(value, _)| filter(value)),
+        };
+
+        match nearest {
+            Some((value, distance)) => (value, LookupReason::Nearest { skipped, distance }),
+            None => (&self.default, LookupReason::Outside { skipped }),
+        }
+    }
+
+    /// Values accepted by the filter, in the order lookups fall through them:
+    /// every matching polygon, the nearest one if none matches
+    /// (with the nearest fallback), the default value always last
+    pub fn lookup_chain_by(
+        &self,
+        coords: Option<&Point<V>>,
+        filter: impl Fn(&T) -> bool,
+    ) -> Vec<&T> {
+        let mut chain = coords
             .map(|coords| {
+                let matching = self
+                    .lookup_all(coords)
+                    .filter(|value| filter(value))
+                    .collect::<Vec<_>>();
+
+                match self.fallback {
+                    Fallback::Nearest { max_distance } if matching.is_empty() => self
+                        .nearest_by(coords, max_distance, &filter)
+                        .into_iter()
+                        .collect(),
+                    _ => matching,
+                }
+            })
+            .unwrap_or_default();
+
+        chain.push(&self.default);
+        chain
+    }
+
+    /// Value of the polygon nearest to the point, if within `max_distance`
+    pub fn nearest(&self, coords: &Point<V>, max_distance: Option<V>) -> Option<&T> {
+        self.nearest_by(coords, max_distance, |_| true)
+    }
+
+    /// Value of the nearest polygon accepted by the filter, if within `max_distance`
+    pub fn nearest_by(
+        &self,
+        coords: &Point<V>,
+        max_distance: Option<V>,
+        filter: impl Fn(&T) -> bool,
+    ) -> Option<&T> {
+        self.nearest_entries(coords, max_distance)
+            .map(|(entry, _)| &self.values[entry.value_index()])
+            .find(|value| filter(value))
+    }
+
+    /// Index entries along with their distance to the point, nearest first, up to `max_distance`
+    ///
+    /// Great circle distances don't follow the planar R-tree order,
+    /// in the geographic system entries are searched within growing distances instead.
+    fn nearest_entries<'a>(
+        &'a self,
+        coords: &'a Point<V>,
+        max_distance: Option<V>,
+    ) -> Box<dyn Iterator<Item = (&'a IndexEntry<V>, V)> + 'a> {
+        let within = move |(_, distance): &(&IndexEntry<V>, V)| {
+            max_distance
+                .map(|max_distance| *distance <= max_distance)
+                .unwrap_or(true)
+        };
+
+        match self.system {
+            CoordinateSystem::Planar => Box::new(
                 self.index
-                    .locate_all_at_point(&[coords.x(), coords.y()])
-                    .filter_map(move |entry| {
-                        if entry.contains(&coords) {
-                            Some(&self.values[entry.value_index()])
-                        } else {
-                            None
-                        }
+                    .nearest_neighbor_iter(&[coords.x(), coords.y()])
+                    .map(move |entry| (entry, entry.distance(coords)))
+                    .take_while(within),
+            ),
+            CoordinateSystem::Geographic => Box::new(
+                GeographicNearest::new(&self.index, coords, max_distance).take_while(within),
+            ),
+        }
+    }
+
+    /// All values with polygons in the index, without the default value
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.values.iter()
+    }
+
+    pub fn default(&self) -> &T {
+        &self.default
+    }
+
+    /// Intersections between polygons of different values, in declaration order
+    ///
+    /// Only polygons with intersecting bounding boxes are compared,
+    /// polygons merely sharing a border don't overlap.
+    pub fn overlaps(&self) -> Vec<Overlap<'_, T, V>> {
+        let mut overlaps = self
+            .index
+            .iter()
+            .flat_map(|first| {
+                self.index
+                    .locate_in_envelope_intersecting(&first.envelope())
+                    .filter(move |second| {
+                        first.value_index() != second.value_index()
+                            && first.order() < second.order()
+                    })
+                    .filter_map(move |second| {
+                        let (area, bounds) = intersection(first.polygon(), second.polygon())?;
+
+                        Some((first, second, area, bounds))
                     })
-                    .nth(0)
-                    .unwrap_or(&self.default)
             })
-            .unwrap_or(&self.default)
+            .collect::<Vec<_>>();
+
+        overlaps.sort_by_key(|(first, second, _, _)| (first.order(), second.order()));
+
+        overlaps
+            .into_iter()
+            .map(|(first, second, area, bounds)| Overlap {
+                first: (&self.values[first.value_index()], first.polygon_index()),
+                second: (&self.values[second.value_index()], second.polygon_index()),
+                area,
+                bounds,
+            })
+            .collect()
+    }
+
+    /// Parts of the region not covered by any polygon of the index
+    ///
+    /// The uncovered parts are the region with the union of all polygons subtracted,
+    /// only polygons with bounding boxes intersecting the region's one are considered.
+    pub fn coverage(&self, region: &Polygon<V>) -> Coverage<V> {
+        match self.system {
+            CoordinateSystem::Planar => self.part_coverage(region),
+            CoordinateSystem::Geographic => split_antimeridian(region).iter().fold(
+                Coverage {
+                    uncovered: MultiPolygon(Vec::new()),
+                    uncovered_area: V::zero(),
+                    region_area: V::zero(),
+                },
+                |mut coverage, part| {
+                    let part = self.part_coverage(part);
+
+                    coverage.uncovered.0.extend(part.uncovered.0);
+                    coverage.uncovered_area = coverage.uncovered_area + part.uncovered_area;
+                    coverage.region_area = coverage.region_area + part.region_area;
+                    coverage
+                },
+            ),
+        }
+    }
+
+    fn part_coverage(&self, region: &Polygon<V>) -> Coverage<V> {
+        let polygons = match region.bounding_rect() {
+            Some(bounds) => self
+                .index
+                .locate_in_envelope_intersecting(&AABB::from_corners(
+                    [bounds.min.x, bounds.min.y],
+                    [bounds.max.x, bounds.max.y],
+                ))
+                .map(IndexEntry::polygon)
+                .collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
+        let (uncovered, uncovered_area) = uncovered(region, &polygons);
+
+        Coverage {
+            uncovered,
+            uncovered_area,
+            region_area: polygon_area(region),
+        }
+    }
+
+    /// All values with a polygon containing the point, without duplicates,
+    /// ordered according to the overlap strategy
+    pub fn lookup_all(&self, coords: &Point<V>) -> impl Iterator<Item = &T> + '_ {
+        let mut entries = self.matching_entries(*coords).collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.cmp_by(b, self.strategy));
+
+        let mut seen = HashSet::new();
+
+        entries
+            .into_iter()
+            .filter(move |entry| seen.insert(entry.value_index()))
+            .map(move |entry| &self.values[entry.value_index()])
     }
 }
 
@@ -112,7 +407,7 @@ mod tests {
         let polygons_1 = vec![rect!(f32 0, 0, 10, 10), rect!(f32 10, 0, 20, 10)];
         let polygons_2 = vec![rect!(f32 0, 10, 10, 20), rect!(f32 10, 10, 20, 20)];
 
-        vec![(polygons_1, 1), (polygons_2, 2)]
+        vec![(polygons_1, 1).into(), (polygons_2, 2).into()]
     }
 
     #[test]
@@ -140,6 +435,102 @@ mod tests {
         assert_eq!(db.lookup_coords(None), &0);
     }
 
+    #[test]
+    fn nearest() {
+        use geo::polygon;
+
+        // envelope of the triangle is closer to the point than the square
+        let triangle = polygon![
+            (x: 0f32, y: 0f32),
+            (x: 10f32, y: 0f32),
+            (x: 0f32, y: 10f32),
+        ];
+        let defs = vec![(vec![triangle], 1), (vec![rect!(f32 12, 12, 14, 14)], 2)];
+        let db = GeoIndex::new(defs, 0);
+
+        assert_eq!(db.nearest(&point!(9f32, 9f32), None), Some(&2));
+        assert_eq!(db.nearest(&point!(6f32, -1f32), None), Some(&1));
+        assert_eq!(db.nearest(&point!(6f32, -1f32), Some(0.5)), None);
+    }
+
+    #[test]
+    fn fallback_nearest() {
+        let defs = simple_data();
+        let db = GeoIndex::new(defs, 0).with_fallback(Fallback::Nearest {
+            max_distance: Some(10.0),
+        });
+
+        assert_eq!(db.lookup_coords(Some(&point!(5f32, -5f32))), &1);
+        assert_eq!(db.lookup_coords(Some(&point!(5f32, 25f32))), &2);
+        assert_eq!(db.lookup_coords(Some(&point!(45f32, 5f32))), &0);
+        assert_eq!(db.lookup_coords(None), &0);
+    }
+
+    #[test]
+    fn lookup_filtered() {
+        let db = GeoIndex::new(overlapping_data(), 0).with_strategy(OverlapStrategy::SmallestArea);
+
+        assert_eq!(
+            db.lookup_coords_by(Some(&point!(5f32, 5f32)), |v| *v != 2),
+            &1
+        );
+        assert_eq!(
+            db.lookup_coords_by(Some(&point!(5f32, 5f32)), |v| *v == 3),
+            &3
+        );
+        assert_eq!(
+            db.lookup_coords_by(Some(&point!(5f32, 5f32)), |_| false),
+            &0
+        );
+    }
+
+    #[test]
+    fn explain() {
+        let db = GeoIndex::new(overlapping_data(), 0)
+            .with_strategy(OverlapStrategy::SmallestArea)
+            .with_fallback(Fallback::Nearest {
+                max_distance: Some(10.0),
+            });
+
+        assert_eq!(db.explain(None), (&0, LookupReason::NoLocation));
+        assert_eq!(
+            db.explain_by(Some(&point!(5f32, 5f32)), |v| *v != 2),
+            (&1, LookupReason::Area { skipped: 1 })
+        );
+        assert_eq!(
+            db.explain_by(Some(&point!(5f32, 5f32)), |_| false),
+            (&0, LookupReason::Outside { skipped: 3 })
+        );
+
+        assert_eq!(
+            db.explain_by(Some(&point!(-5f32, 5f32)), |v| *v == 3),
+            (
+                &3,
+                LookupReason::Nearest {
+                    skipped: 0,
+                    distance: 5.0
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn nearest_filtered() {
+        let defs = simple_data();
+        let db = GeoIndex::new(defs, 0).with_fallback(Fallback::Nearest {
+            max_distance: Some(12.0),
+        });
+
+        assert_eq!(
+            db.lookup_coords_by(Some(&point!(5f32, -1f32)), |v| *v != 1),
+            &2
+        );
+        assert_eq!(
+            db.lookup_coords_by(Some(&point!(5f32, -5f32)), |v| *v != 1),
+            &0
+        );
+    }
+
     #[test]
     fn open() {
         use geo::polygon;
@@ -155,4 +546,568 @@ mod tests {
 
         assert_eq!(db.lookup_coords(Some(&point!(2f32, 1f32))), &1);
     }
+
+    fn overlapping_data() -> IndexDefinition<u32, f32> {
+        vec![
+            AreaDefinition {
+                polygons: vec![rect!(f32 0, 0, 20, 20)],
+                value: 1,
+                priority: None,
+            },
+            AreaDefinition {
+                polygons: vec![rect!(f32 0, 0, 10, 10)],
+                value: 2,
+                priority: Some(-1),
+            },
+            AreaDefinition {
+                polygons: vec![rect!(f32 0, 0, 30, 30)],
+                value: 3,
+                priority: Some(5),
+            },
+        ]
+    }
+
+    #[test]
+    fn overlap_first_declared() {
+        let db = GeoIndex::new(overlapping_data(), 0);
+
+        assert_eq!(db.lookup_coords(Some(&point!(5f32, 5f32))), &1);
+        assert_eq!(db.lookup_coords(Some(&point!(25f32, 25f32))), &3);
+    }
+
+    #[test]
+    fn overlap_highest_priority() {
+        let db =
+            GeoIndex::new(overlapping_data(), 0).with_strategy(OverlapStrategy::HighestPriority);
+
+        assert_eq!(db.lookup_coords(Some(&point!(5f32, 5f32))), &3);
+    }
+
+    #[test]
+    fn overlap_smallest_area() {
+        let db = GeoIndex::new(overlapping_data(), 0).with_strategy(OverlapStrategy::SmallestArea);
+
+        assert_eq!(db.lookup_coords(Some(&point!(5f32, 5f32))), &2);
+        assert_eq!(db.lookup_coords(Some(&point!(15f32, 15f32))), &1);
+    }
+
+    #[test]
+    fn lookup_all() {
+        let db = GeoIndex::new(overlapping_data(), 0).with_strategy(OverlapStrategy::SmallestArea);
+
+        assert_eq!(
+            db.lookup_all(&point!(5f32, 5f32)).collect::<Vec<_>>(),
+            vec![&2, &1, &3]
+        );
+        assert_eq!(
+            db.lookup_all(&point!(25f32, 25f32)).collect::<Vec<_>>(),
+            vec![&3]
+        );
+        assert_eq!(db.lookup_all(&point!(45f32, 5f32)).count(), 0);
+    }
+
+    #[test]
+    fn lookup_chain() {
+        let polygons = vec![
+            (vec![rect!(f32 0, 0, 10, 10)], 1),
+            (vec![rect!(f32 5, 5, 15, 15)], 2),
+            (vec![rect!(f32 6, 6, 8, 8)], 3),
+        ];
+        let db = GeoIndex::new(polygons, 0).with_fallback(Fallback::Nearest { max_distance: None });
+
+        assert_eq!(
+            db.lookup_chain_by(Some(&point!(7f32, 7f32)), |value| *value != 3),
+            vec![&1, &2, &0]
+        );
+        assert_eq!(
+            db.lookup_chain_by(Some(&point!(20f32, 20f32)), |_| true),
+            vec![&2, &0]
+        );
+        assert_eq!(db.lookup_chain_by(None, |_| true), vec![&0]);
+
+        // the head of the chain is the regular lookup result
+        assert_eq!(
+            db.lookup_chain_by(Some(&point!(7f32, 7f32)), |_| true)[0],
+            db.lookup_coords(Some(&point!(7f32, 7f32)))
+        );
+    }
+
+    #[test]
+    fn lookup_all_unique() {
+        let polygons = vec![rect!(f32 0, 0, 10, 10), rect!(f32 5, 5, 15, 15)];
+        let db = GeoIndex::new(vec![(polygons, 1)], 0);
+
+        assert_eq!(
+            db.lookup_all(&point!(7f32, 7f32)).collect::<Vec<_>>(),
+            vec![&1]
+        );
+    }
+
+    #[test]
+    fn overlap_ties() {
+        let defs = vec![
+            AreaDefinition {
+                polygons: vec![rect!(f32 0, 0, 10, 10)],
+                value: 1,
+                priority: Some(1),
+            },
+            AreaDefinition {
+                polygons: vec![rect!(f32 5, 5, 15, 15)],
+                value: 2,
+                priority: Some(1),
+            },
+        ];
+
+        // same priority and area, declaration order decides
+        let db = GeoIndex::new(defs, 0).with_strategy(OverlapStrategy::HighestPriority);
+
+        assert_eq!(db.lookup_coords(Some(&point!(7f32, 7f32))), &1);
+    }
+
+    #[test]
+    fn overlaps() {
+        let db = GeoIndex::new(overlapping_data(), 0);
+        let overlaps = db
+            .overlaps()
+            .into_iter()
+            .map(|overlap| (*overlap.first.0, *overlap.second.0, overlap.area))
+            .collect::<Vec<_>>();
+
+        assert_eq!(overlaps, vec![(1, 2, 100.), (1, 3, 400.), (2, 3, 100.)]);
+
+        let overlap = &db.overlaps()[0];
+        assert_eq!((overlap.first.1, overlap.second.1), (0, 0));
+        assert_eq!(overlap.bounds.min, (0f32, 0f32).into());
+        assert_eq!(overlap.bounds.max, (10f32, 10f32).into());
+    }
+
+    #[test]
+    fn overlaps_shared_border() {
+        let db = GeoIndex::new(simple_data(), 0);
+
+        assert_eq!(db.overlaps(), vec![]);
+    }
+
+    #[test]
+    fn overlaps_hole() {
+        use geo::polygon;
+
+        let frame = polygon!(
+            exterior: [(x: 0f32, y: 0f32), (x: 10f32, y: 0f32), (x: 10f32, y: 10f32), (x: 0f32, y: 10f32)],
+            interiors: [[(x: 2f32, y: 2f32), (x: 2f32, y: 8f32), (x: 8f32, y: 8f32), (x: 8f32, y: 2f32)]],
+        );
+        let defs = vec![
+            (vec![frame], 1),
+            (vec![rect!(f32 3, 3, 7, 7)], 2),
+            (vec![rect!(f32 6, 6, 12, 12)], 3),
+        ];
+        let db = GeoIndex::new(defs, 0);
+        let overlaps = db.overlaps();
+
+        // the frame around the hole overlaps, the polygon within the hole doesn't
+        assert_eq!(overlaps.len(), 2);
+        assert_eq!((*overlaps[0].first.0, *overlaps[0].second.0), (1, 3));
+        assert!((overlaps[0].area - 12.).abs() < 1e-4);
+        assert_eq!((*overlaps[1].first.0, *overlaps[1].second.0), (2, 3));
+        assert!((overlaps[1].area - 1.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn coverage() {
+        let db = GeoIndex::new(simple_data(), 0);
+
+        let covered = db.coverage(&rect!(f32 5, 5, 15, 15));
+        assert_eq!(covered.uncovered.0.len(), 0);
+        assert_eq!(covered.uncovered_area, 0.);
+
+        let coverage = db.coverage(&rect!(f32 0, 0, 30, 20));
+        assert_eq!(coverage.uncovered.0.len(), 1);
+        assert_eq!(coverage.uncovered.0[0].exterior().0.len(), 5);
+        assert_eq!(
+            coverage.uncovered.0[0].bounding_rect().unwrap().min,
+            (20f32, 0f32).into()
+        );
+        assert_eq!(
+            coverage.uncovered.0[0].bounding_rect().unwrap().max,
+            (30f32, 20f32).into()
+        );
+        assert_eq!(coverage.uncovered_area, 200.);
+        assert_eq!(coverage.region_area, 600.);
+        assert!((coverage.uncovered_percentage() - 100. / 3.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn coverage_diagonal() {
+        use geo::polygon;
+
+        let triangle = polygon![(x: 0f32, y: 0f32), (x: 10f32, y: 0f32), (x: 0f32, y: 10f32)];
+        let db = GeoIndex::new(vec![(vec![triangle], 1)], 0);
+        let coverage = db.coverage(&rect!(f32 0, 0, 10, 10));
+
+        // the remaining triangle, without the slab borders
+        assert_eq!(coverage.uncovered.0.len(), 1);
+        assert_eq!(coverage.uncovered.0[0].exterior().0.len(), 4);
+        assert!((coverage.uncovered_area - 50.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn coverage_holes() {
+        use geo::polygon;
+
+        let frame = polygon!(
+            exterior: [(x: 0f32, y: 0f32), (x: 10f32, y: 0f32), (x: 10f32, y: 10f32), (x: 0f32, y: 10f32)],
+            interiors: [[(x: 2f32, y: 2f32), (x: 2f32, y: 8f32), (x: 8f32, y: 8f32), (x: 8f32, y: 2f32)]],
+        );
+        let db = GeoIndex::new(vec![(vec![frame], 1)], 0);
+
+        // the hole of the polygon is left uncovered
+        let coverage = db.coverage(&rect!(f32 0, 0, 10, 10));
+        assert_eq!(coverage.uncovered.0.len(), 1);
+        assert_eq!(coverage.uncovered.0[0].interiors().len(), 0);
+        assert_eq!(coverage.uncovered_area, 36.);
+
+        // the polygon makes a hole in the uncovered part of a larger region
+        let coverage = db.coverage(&rect!(f32 - 5, -5, 15, 15));
+        assert_eq!(coverage.uncovered.0.len(), 2);
+        assert_eq!(
+            coverage
+                .uncovered
+                .0
+                .iter()
+                .map(|part| part.interiors().len())
+                .sum::<usize>(),
+            1
+        );
+        assert_eq!(coverage.uncovered_area, 336.);
+
+        // polygons touching at a corner make separate holes
+        let defs = vec![
+            (vec![rect!(f32 0, 0, 5, 5)], 1),
+            (vec![rect!(f32 - 5, -5, 0, 0)], 2),
+        ];
+        let coverage = GeoIndex::new(defs, 0).coverage(&rect!(f32 - 10, -10, 10, 10));
+        assert_eq!(coverage.uncovered.0.len(), 1);
+        assert_eq!(coverage.uncovered.0[0].interiors().len(), 2);
+        assert_eq!(coverage.uncovered_area, 350.);
+    }
+
+    #[test]
+    fn coverage_region_holes() {
+        use geo::polygon;
+
+        let region = polygon!(
+            exterior: [(x: 0f32, y: 0f32), (x: 20f32, y: 0f32), (x: 20f32, y: 20f32), (x: 0f32, y: 20f32)],
+            interiors: [[(x: 5f32, y: 5f32), (x: 5f32, y: 15f32), (x: 15f32, y: 15f32), (x: 15f32, y: 5f32)]],
+        );
+
+        // the hole of the region is never uncovered
+        let coverage =
+            GeoIndex::new(vec![(vec![rect!(f32 30, 30, 40, 40)], 1)], 0).coverage(&region);
+        assert_eq!(coverage.uncovered.0.len(), 1);
+        assert_eq!(coverage.uncovered.0[0].interiors().len(), 1);
+        assert_eq!(coverage.uncovered_area, 300.);
+        assert_eq!(coverage.region_area, 300.);
+
+        // a polygon covering the hole and the frame around it, leaving the outer frame
+        let db = GeoIndex::new(vec![(vec![rect!(f32 2, 2, 18, 18)], 1)], 0);
+        let coverage = db.coverage(&region);
+        assert_eq!(coverage.uncovered.0.len(), 1);
+        assert_eq!(coverage.uncovered.0[0].interiors().len(), 1);
+        assert_eq!(coverage.uncovered_area, 144.);
+
+        // a polygon within the hole covers nothing
+        let db = GeoIndex::new(vec![(vec![rect!(f32 6, 6, 14, 14)], 1)], 0);
+        assert_eq!(db.coverage(&region).uncovered_area, 300.);
+    }
+
+    #[test]
+    fn coverage_touching_edges() {
+        // squares sharing edges, one of them only along part of its edge
+        let defs = vec![
+            (vec![rect!(f32 0, 0, 10, 10)], 1),
+            (vec![rect!(f32 10, 0, 20, 10)], 2),
+            (vec![rect!(f32 0, 10, 5, 20)], 3),
+            (vec![rect!(f32 5, 10, 20, 20)], 4),
+        ];
+        let db = GeoIndex::new(defs, 0);
+
+        let coverage = db.coverage(&rect!(f32 0, 0, 20, 20));
+        assert_eq!(coverage.uncovered.0.len(), 0);
+        assert_eq!(coverage.uncovered_area, 0.);
+
+        // the region touching the polygons from outside is left whole
+        let coverage = db.coverage(&rect!(f32 20, 0, 30, 20));
+        assert_eq!(coverage.uncovered.0.len(), 1);
+        assert_eq!(coverage.uncovered.0[0].exterior().0.len(), 5);
+        assert_eq!(coverage.uncovered_area, 200.);
+
+        // triangles sharing their diagonal
+        use geo::polygon;
+
+        let lower = polygon![(x: 0f32, y: 0f32), (x: 10f32, y: 0f32), (x: 10f32, y: 10f32)];
+        let upper = polygon![(x: 0f32, y: 0f32), (x: 10f32, y: 10f32), (x: 0f32, y: 10f32)];
+        let db = GeoIndex::new(vec![(vec![lower], 1), (vec![upper], 2)], 0);
+        let coverage = db.coverage(&rect!(f32 0, 0, 10, 10));
+        assert_eq!(coverage.uncovered.0.len(), 0);
+        assert!(coverage.uncovered_area.abs() < 1e-4);
+    }
+
+    #[test]
+    fn coverage_collinear_overlaps() {
+        use geo::polygon;
+
+        // rectangles overlapping along their collinear bottom and top edges
+        let defs = vec![
+            (vec![rect!(f32 0, 0, 10, 10)], 1),
+            (vec![rect!(f32 5, 0, 15, 10)], 2),
+            (vec![rect!(f32 12, 0, 20, 10)], 3),
+        ];
+        let db = GeoIndex::new(defs, 0);
+        let coverage = db.coverage(&rect!(f32 0, 0, 25, 10));
+        assert_eq!(coverage.uncovered.0.len(), 1);
+        assert_eq!(coverage.uncovered.0[0].exterior().0.len(), 5);
+        assert_eq!(coverage.uncovered_area, 50.);
+
+        // overlapping edges of the region and the polygon, on a slope
+        let region = polygon![(x: 0f32, y: 0f32), (x: 20f32, y: 0f32), (x: 20f32, y: 20f32)];
+        let part = polygon![(x: 0f32, y: 0f32), (x: 10f32, y: 0f32), (x: 10f32, y: 10f32)];
+        let db = GeoIndex::new(vec![(vec![part], 1)], 0);
+        let coverage = db.coverage(&region);
+        assert_eq!(coverage.uncovered.0.len(), 1);
+        assert_eq!(coverage.uncovered.0[0].exterior().0.len(), 5);
+        assert!((coverage.uncovered_area - 150.).abs() < 1e-4);
+
+        // a gap between collinear rectangles
+        let defs = vec![
+            (vec![rect!(f32 0, 0, 8, 10)], 1),
+            (vec![rect!(f32 12, 0, 20, 10)], 2),
+        ];
+        let coverage = GeoIndex::new(defs, 0).coverage(&rect!(f32 0, 0, 20, 10));
+        assert_eq!(coverage.uncovered.0.len(), 1);
+        assert_eq!(
+            coverage.uncovered.0[0].bounding_rect().unwrap(),
+            geo::Rect {
+                min: (8f32, 0f32).into(),
+                max: (12f32, 10f32).into(),
+            }
+        );
+        assert_eq!(coverage.uncovered_area, 40.);
+    }
+
+    fn antimeridian_data() -> IndexDefinition<u32, f32> {
+        use geo::polygon;
+
+        // from 170° east to 170° west, across the antimeridian
+        let pacific = polygon![
+            (x: 170f32, y: 0f32),
+            (x: -170f32, y: 0f32),
+            (x: -170f32, y: 10f32),
+            (x: 170f32, y: 10f32),
+        ];
+
+        vec![(vec![pacific], 1).into()]
+    }
+
+    #[test]
+    fn geographic_antimeridian() {
+        let planar = GeoIndex::new(antimeridian_data(), 0);
+        let geographic = GeoIndex::new(antimeridian_data(), 0)
+            .with_coordinate_system(CoordinateSystem::Geographic);
+
+        assert_eq!(planar.lookup_coords(Some(&point!(0., 5.))), &1);
+        assert_eq!(planar.lookup_coords(Some(&point!(175., 5.))), &0);
+
+        assert_eq!(geographic.lookup_coords(Some(&point!(0., 5.))), &0);
+        assert_eq!(geographic.lookup_coords(Some(&point!(175., 5.))), &1);
+        assert_eq!(geographic.lookup_coords(Some(&point!(-175., 5.))), &1);
+        assert_eq!(geographic.overlaps(), vec![]);
+
+        let coverage = geographic.coverage(&rect!(f32 160, 0, 180, 10));
+        assert!((coverage.uncovered_area - 100.).abs() < 1e-3);
+
+        // a region across the antimeridian, from 160° east to 160° west
+        use geo::polygon;
+
+        let region = polygon![
+            (x: 160f32, y: 0f32),
+            (x: -160f32, y: 0f32),
+            (x: -160f32, y: 10f32),
+            (x: 160f32, y: 10f32),
+        ];
+        let coverage = geographic.coverage(&region);
+        assert_eq!(coverage.uncovered.0.len(), 2);
+        assert!((coverage.uncovered_area - 200.).abs() < 1e-3);
+        assert!((coverage.region_area - 400.).abs() < 1e-3);
+
+        let mut bounds = coverage
+            .uncovered
+            .0
+            .iter()
+            .map(|part| part.bounding_rect().unwrap())
+            .collect::<Vec<_>>();
+        bounds.sort_by(|a, b| a.min.x.partial_cmp(&b.min.x).unwrap());
+        assert_eq!((bounds[0].min.x, bounds[0].max.x), (-170., -160.));
+        assert_eq!((bounds[1].min.x, bounds[1].max.x), (160., 170.));
+    }
+
+    #[test]
+    fn geographic_nearest() {
+        // near the pole, the polygon far to the east is nearer than the one to the south
+        let defs = vec![
+            (vec![rect!(f32 60, 80, 70, 81)], 1),
+            (vec![rect!(f32 - 5, 68, 5, 69)], 2),
+        ];
+        let planar = GeoIndex::new(defs.clone(), 0);
+        let geographic =
+            GeoIndex::new(defs, 0).with_coordinate_system(CoordinateSystem::Geographic);
+        let pole = point!(0., 80.);
+
+        assert_eq!(planar.nearest(&pole, None), Some(&2));
+        assert_eq!(geographic.nearest(&pole, None), Some(&1));
+        assert_eq!(geographic.nearest(&pole, Some(1_000_000.)), None);
+
+        let geographic = geographic.with_fallback(Fallback::Nearest { max_distance: None });
+        let (value, reason) = geographic.explain(Some(&pole));
+        assert_eq!(value, &1);
+        match reason {
+            // about 1057 km along the great circle, to the corner at 60°E 81°N
+            LookupReason::Nearest { distance, .. } => {
+                assert!((distance - 1_057_000.).abs() < 2_000.)
+            }
+            other => panic!("unexpected reason {:?}", other),
+        }
+    }
+
+    #[test]
+    fn geographic_validation() {
+        use geo::{line_string, Coordinate};
+
+        let system = CoordinateSystem::Geographic;
+
+        assert!(system.is_valid(Coordinate {
+            x: -180f32,
+            y: 90f32
+        }));
+        assert!(!system.is_valid(Coordinate { x: 181f32, y: 0f32 }));
+        assert!(!system.is_valid(Coordinate { x: 0f32, y: -91f32 }));
+        assert!(CoordinateSystem::Planar.is_valid(Coordinate { x: 181f32, y: 0f32 }));
+
+        let polar = line_string![
+            (x: 0f32, y: 80f32),
+            (x: 120f32, y: 80f32),
+            (x: -120f32, y: 80f32),
+            (x: 0f32, y: 80f32),
+        ];
+        assert!(system.encircles_pole(&polar));
+        assert!(!system.encircles_pole(antimeridian_data()[0].polygons[0].exterior()));
+    }
+    #[test]
+    fn great_circle_bulge() {
+        // the edge along the 60th parallel bulges north, the one along the equator doesn't
+        use geo::{algorithm::map_coords::MapCoords, polygon};
+
+        let polygon = polygon![
+            (x: 0f64, y: 0f64),
+            (x: 90f64, y: 0f64),
+            (x: 90f64, y: 60f64),
+            (x: 0f64, y: 60f64),
+        ];
+        let (min, max) = geographic::latitude_range(&polygon);
+
+        assert_eq!(min, 0.);
+        // the midpoint of the arc lies at atan(tan 60° / cos 45°)
+        assert!((max - 67.792).abs() < 1e-3, "{}", max);
+
+        // mirrored on the southern hemisphere
+        let polygon = polygon.map_coords(&|&(x, y)| (x, -y));
+        assert!((geographic::latitude_range(&polygon).0 + 67.792).abs() < 1e-3);
+    }
+
+    #[test]
+    fn boxes_within_distance() {
+        let boxes = geographic::bounding_boxes(&Point::new(10f64, 45f64), 111_195.);
+        assert_eq!(boxes.len(), 1);
+        assert!((boxes[0].min.y - 44.).abs() < 1e-3);
+        assert!((boxes[0].max.y - 46.).abs() < 1e-3);
+        // meridians converge, a degree of longitude is shorter at 45°
+        assert!((boxes[0].max.x - 11.414).abs() < 1e-3, "{}", boxes[0].max.x);
+
+        let boxes = geographic::bounding_boxes(&Point::new(179.5f64, 0f64), 111_195.);
+        assert_eq!(boxes.len(), 2);
+        assert_eq!(boxes[0].max.x, 180.);
+        assert!((boxes[1].max.x + 179.5).abs() < 1e-3);
+
+        let boxes = geographic::bounding_boxes(&Point::new(0f64, 89.5f64), 111_195.);
+        assert_eq!(boxes.len(), 1);
+        assert_eq!((boxes[0].min.x, boxes[0].max.y), (-180., 90.));
+    }
+
+    #[test]
+    fn geographic_nearest_order() {
+        use crate::geographic::distance;
+        use geo::polygon;
+
+        // pseudo random boxes all over the globe, some across the antimeridian or near the poles
+        let mut seed = 42u64;
+        let mut random = move |min: f64, max: f64| {
+            seed = seed
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+
+            min + (max - min) * ((seed >> 11) as f64 / (1u64 << 53) as f64)
+        };
+
+        let defs = (0..200)
+            .map(|value| {
+                let (x, y) = (random(-180., 180.), random(-89., 85.));
+                let (width, height) = (random(0.1, 20.), random(0.1, 4.));
+                let east = if x + width > 180. {
+                    x + width - 360.
+                } else {
+                    x + width
+                };
+
+                let polygon = polygon![
+                    (x: x, y: y),
+                    (x: east, y: y),
+                    (x: east, y: y + height),
+                    (x: x, y: y + height),
+                ];
+
+                (vec![polygon], value)
+            })
+            .collect::<Vec<_>>();
+        let db = GeoIndex::new(defs, 0).with_coordinate_system(CoordinateSystem::Geographic);
+
+        for _ in 0..50 {
+            let point = point!(random(-180., 180.), random(-90., 90.));
+            let max_distance = Some(random(0., 5_000_000.)).filter(|_| random(0., 1.) < 0.5);
+
+            let mut expected = db
+                .index
+                .iter()
+                .map(|entry| {
+                    let distance = if entry.contains(&point) {
+                        0.
+                    } else {
+                        distance(&point, entry.polygon())
+                    };
+
+                    (entry.order(), distance)
+                })
+                .filter(|(_, distance)| max_distance.map(|max| *distance <= max).unwrap_or(true))
+                .collect::<Vec<_>>();
+            expected.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(a.0.cmp(&b.0)));
+
+            let mut found = db
+                .nearest_entries(&point, max_distance)
+                .map(|(entry, distance)| (entry.order(), distance))
+                .collect::<Vec<_>>();
+            // nearest first, entries at the same distance in any order
+            assert!(found.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+            found.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(a.0.cmp(&b.0)));
+
+            assert_eq!(found, expected, "nearest to {:?}", point);
+        }
+    }
 }
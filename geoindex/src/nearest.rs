@@ -0,0 +1,126 @@
+use geo::Point;
+use hashbrown::HashSet;
+use rstar::{self, RTree, AABB};
+
+use std::cmp::Ordering;
+
+use crate::entry::IndexEntry;
+use crate::geographic::{bounding_boxes, constant, distance, EARTH_RADIUS};
+use crate::ty::IndexCoordinate;
+
+/// Index entries by great circle distance to a point, nearest first
+///
+/// The search starts with the distance of the entry nearest in the planar sense,
+/// and doubles until it reaches `max_distance` or the antipode. Every round only
+/// entries with envelopes intersecting the bounding boxes of the distance are measured.
+pub(crate) struct GeographicNearest<'a, V: IndexCoordinate>
+where
+    [V; 2]: rstar::Point<Scalar = V>,
+{
+    index: &'a RTree<IndexEntry<V>>,
+    point: &'a Point<V>,
+    max_distance: Option<V>,
+    /// Farthest distance searched, the distance to the antipode without `max_distance`
+    limit: V,
+    /// Distance within which every entry was measured, none before the first round
+    searched: Option<V>,
+    measured: HashSet<*const IndexEntry<V>>,
+    /// Entries measured but not returned yet, farthest first
+    pending: Vec<(&'a IndexEntry<V>, V)>,
+}
+
+impl<'a, V: IndexCoordinate> GeographicNearest<'a, V>
+where
+    [V; 2]: rstar::Point<Scalar = V>,
+{
+    pub(crate) fn new(
+        index: &'a RTree<IndexEntry<V>>,
+        point: &'a Point<V>,
+        max_distance: Option<V>,
+    ) -> Self {
+        let antipode = constant::<V>(EARTH_RADIUS * std::f64::consts::PI);
+
+        Self {
+            index,
+            point,
+            max_distance,
+            limit: max_distance
+                .map(|max| max.min(antipode))
+                .unwrap_or(antipode),
+            searched: None,
+            measured: HashSet::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    fn measure(&self, entry: &IndexEntry<V>) -> V {
+        if entry.contains(self.point) {
+            V::zero()
+        } else {
+            distance(self.point, entry.polygon())
+        }
+    }
+
+    /// Distance of the next round, none once the limit was searched
+    fn next_distance(&self) -> Option<V> {
+        let next = match self.searched {
+            None => {
+                let nearest = self
+                    .index
+                    .nearest_neighbor_iter(&[self.point.x(), self.point.y()])
+                    .next()?;
+
+                self.measure(nearest)
+            }
+            Some(searched) if searched >= self.limit => return None,
+            // a kilometer after a round finding only the polygons containing the point
+            Some(searched) if searched == V::zero() => constant(1000.),
+            Some(searched) => searched + searched,
+        };
+
+        Some(next.min(self.limit))
+    }
+
+    fn search(&mut self, distance: V) {
+        let boxes = bounding_boxes(self.point, distance);
+
+        for bounds in boxes {
+            let envelope =
+                AABB::from_corners([bounds.min.x, bounds.min.y], [bounds.max.x, bounds.max.y]);
+
+            for entry in self.index.locate_in_envelope_intersecting(&envelope) {
+                if self.measured.insert(entry as *const _) {
+                    self.pending.push((entry, self.measure(entry)));
+                }
+            }
+        }
+
+        self.pending
+            .sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        self.searched = Some(distance);
+    }
+}
+
+impl<'a, V: IndexCoordinate> Iterator for GeographicNearest<'a, V>
+where
+    [V; 2]: rstar::Point<Scalar = V>,
+{
+    type Item = (&'a IndexEntry<V>, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // entries farther than the searched distance might be preceded by unmeasured ones,
+            // unless the whole sphere was searched
+            if let (Some(&(_, distance)), Some(searched)) = (self.pending.last(), self.searched) {
+                let whole = self.max_distance.is_none() && searched >= self.limit;
+
+                if distance <= searched || whole {
+                    return self.pending.pop();
+                }
+            }
+
+            let distance = self.next_distance()?;
+            self.search(distance);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use geo::{
+    algorithm::{
+        bounding_rect::BoundingRect,
+        contains::Contains,
+        euclidean_distance::EuclideanDistance,
+        orient::{Direction, Orient},
+    },
+    Coordinate, Line, Point, Polygon, Rect,
+};
+
+use crate::ty::IndexCoordinate;
+
+/// Position of a point relative to a polygon
+enum Position {
+    Inside,
+    Outside,
+    /// On the boundary, along the edge with the given index
+    Boundary(usize),
+}
+
+fn cross<V: IndexCoordinate>(a: Coordinate<V>, b: Coordinate<V>) -> V {
+    a.x * b.y - a.y * b.x
+}
+
+fn dot<V: IndexCoordinate>(a: Coordinate<V>, b: Coordinate<V>) -> V {
+    a.x * b.x + a.y * b.y
+}
+
+fn direction<V: IndexCoordinate>(line: &Line<V>) -> Coordinate<V> {
+    Coordinate {
+        x: line.dx(),
+        y: line.dy(),
+    }
+}
+
+fn at<V: IndexCoordinate>(line: &Line<V>, t: V) -> Coordinate<V> {
+    Coordinate {
+        x: line.start.x + line.dx() * t,
+        y: line.start.y + line.dy() * t,
+    }
+}
+
+/// Edges of the polygon rings, the exterior counter-clockwise and interiors clockwise,
+/// so the polygon always lies to the left of its edges
+fn edges<V: IndexCoordinate>(polygon: &Polygon<V>) -> Vec<Line<V>> {
+    let polygon = polygon.orient(Direction::Default);
+
+    Some(polygon.exterior())
+        .into_iter()
+        .chain(polygon.interiors())
+        .flat_map(|ring| ring.lines())
+        .filter(|line| line.start != line.end)
+        .collect()
+}
+
+/// Positions along the edge, from 0 to 1, where it crosses or touches the other edges
+fn split_points<V: IndexCoordinate>(edge: &Line<V>, others: &[Line<V>]) -> Vec<V> {
+    let d = direction(edge);
+    let mut points = vec![V::zero(), V::one()];
+
+    for other in others {
+        let g = direction(other);
+        let offset = Coordinate {
+            x: other.start.x - edge.start.x,
+            y: other.start.y - edge.start.y,
+        };
+        let denominator = cross(d, g);
+
+        if denominator != V::zero() {
+            let t = cross(offset, g) / denominator;
+            let s = cross(offset, d) / denominator;
+
+            if t >= V::zero() && t <= V::one() && s >= V::zero() && s <= V::one() {
+                points.push(t);
+            }
+        } else if cross(offset, d) == V::zero() {
+            // collinear edges, split where the other one starts and ends
+            let length = dot(d, d);
+
+            for end in &[other.start, other.end] {
+                let offset = Coordinate {
+                    x: end.x - edge.start.x,
+                    y: end.y - edge.start.y,
+                };
+                let t = dot(offset, d) / length;
+
+                if t > V::zero() && t < V::one() {
+                    points.push(t);
+                }
+            }
+        }
+    }
+
+    points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    points.dedup();
+    points
+}
+
+fn position<V: IndexCoordinate>(
+    point: Coordinate<V>,
+    polygon: &Polygon<V>,
+    edges: &[Line<V>],
+    tolerance: V,
+) -> Position {
+    let point = Point(point);
+
+    if let Some(edge) = edges
+        .iter()
+        .position(|edge| point.euclidean_distance(edge) <= tolerance)
+    {
+        Position::Boundary(edge)
+    } else if polygon.contains(&point) {
+        Position::Inside
+    } else {
+        Position::Outside
+    }
+}
+
+/// Parts of the `clipped` edges lying within the other polygon
+///
+/// Parts along the boundary of the other polygon are kept if `shared` is set
+/// and both edges run in the same direction, so they're counted for one of the polygons only.
+fn clip<V: IndexCoordinate>(
+    clipped: &[Line<V>],
+    polygon: &Polygon<V>,
+    edges: &[Line<V>],
+    shared: bool,
+    tolerance: V,
+) -> Vec<Line<V>> {
+    let two = V::one() + V::one();
+
+    clipped
+        .iter()
+        .flat_map(|edge| {
+            split_points(edge, edges)
+                .windows(2)
+                .map(|window| Line::new(at(edge, window[0]), at(edge, window[1])))
+                .filter(|part| part.start != part.end)
+                .filter(|part| {
+                    let middle = at(part, V::one() / two);
+
+                    match position(middle, polygon, edges, tolerance) {
+                        Position::Inside => true,
+                        Position::Outside => false,
+                        Position::Boundary(other) => {
+                            shared && dot(direction(edge), direction(&edges[other])) > V::zero()
+                        }
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Area and bounding box of the intersection of two polygons, none if they don't overlap
+///
+/// The boundary of the intersection consists of the edges of each polygon lying within the other,
+/// its area is calculated from these parts with the shoelace formula.
+pub(crate) fn intersection<V: IndexCoordinate>(
+    first: &Polygon<V>,
+    second: &Polygon<V>,
+) -> Option<(V, Rect<V>)> {
+    let (first_bounds, second_bounds) = (first.bounding_rect()?, second.bounding_rect()?);
+    let scale = [first_bounds, second_bounds]
+        .iter()
+        .flat_map(|bounds| vec![bounds.min.x, bounds.min.y, bounds.max.x, bounds.max.y])
+        .fold(V::one(), |scale, value| scale.max(value.abs()));
+    let tolerance = scale * V::epsilon() * V::from(64).unwrap();
+
+    let (first_edges, second_edges) = (edges(first), edges(second));
+
+    let mut parts = clip(&first_edges, second, &second_edges, true, tolerance);
+    parts.extend(clip(&second_edges, first, &first_edges, false, tolerance));
+
+    let area = parts
+        .iter()
+        .fold(V::zero(), |area, part| area + cross(part.start, part.end))
+        / (V::one() + V::one());
+
+    // anything below the tolerance is a rounding error of polygons sharing a border
+    if area <= tolerance * scale {
+        return None;
+    }
+
+    let bounds = parts
+        .iter()
+        .flat_map(|part| vec![part.start, part.end])
+        .fold(None, |bounds: Option<Rect<V>>, point| {
+            Some(match bounds {
+                Some(bounds) => Rect {
+                    min: Coordinate {
+                        x: bounds.min.x.min(point.x),
+                        y: bounds.min.y.min(point.y),
+                    },
+                    max: Coordinate {
+                        x: bounds.max.x.max(point.x),
+                        y: bounds.max.y.max(point.y),
+                    },
+                },
+                None => Rect {
+                    min: point,
+                    max: point,
+                },
+            })
+        })?;
+
+    Some((area, bounds))
+}
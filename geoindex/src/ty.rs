@@ -1,9 +1,161 @@
-use geo::{CoordinateType, Polygon};
+use geo::{Coordinate, CoordinateType, LineString, MultiPolygon, Polygon, Rect};
 use num_traits::{Bounded, Float, Signed};
 
 use std::fmt::Debug;
 
-pub type IndexDefinition<T, V = f32> = Vec<(Vec<Polygon<V>>, T)>;
+use crate::geographic::{encircles_pole, in_range};
+
+/// Areas covered by a single indexed value
+#[derive(Debug, Clone, PartialEq)]
+pub struct AreaDefinition<T, V: CoordinateType = f32> {
+    pub polygons: Vec<Polygon<V>>,
+    pub value: T,
+    /// Priority used by `OverlapStrategy::HighestPriority`, defaults to 0
+    pub priority: Option<i32>,
+}
+
+impl<T, V: CoordinateType> From<(Vec<Polygon<V>>, T)> for AreaDefinition<T, V> {
+    fn from((polygons, value): (Vec<Polygon<V>>, T)) -> Self {
+        Self {
+            polygons,
+            value,
+            priority: None,
+        }
+    }
+}
+
+pub type IndexDefinition<T, V = f32> = Vec<AreaDefinition<T, V>>;
+
+/// Strategy used to select a value when the looked up point lies within multiple polygons
+///
+/// Declaration order is the order of values in the index definition,
+/// followed by the order of polygons within each value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum OverlapStrategy {
+    /// Polygon declared first wins
+    #[default]
+    FirstDeclared,
+    /// Highest priority wins, ties are broken by the smallest area, then by declaration order
+    HighestPriority,
+    /// Smallest polygon area wins, ties are broken by the highest priority, then by declaration order
+    SmallestArea,
+}
+
+/// Meaning of the polygon and point coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum CoordinateSystem {
+    /// Cartesian `x`, `y` coordinates, distances are measured in coordinate units
+    #[default]
+    Planar,
+    /// Longitude and latitude in degrees, as `x` and `y`
+    ///
+    /// Polygons crossing the antimeridian are split along it,
+    /// distances are measured along great circles, in meters.
+    Geographic,
+}
+
+impl CoordinateSystem {
+    /// Whether the coordinate is finite, and a longitude and latitude within their ranges
+    /// in the geographic system
+    pub fn is_valid<V: IndexCoordinate>(self, coordinate: Coordinate<V>) -> bool {
+        let finite = coordinate.x.is_finite() && coordinate.y.is_finite();
+
+        match self {
+            CoordinateSystem::Planar => finite,
+            CoordinateSystem::Geographic => finite && in_range(coordinate),
+        }
+    }
+
+    /// Whether the ring goes all the way around a pole, such rings can't be indexed
+    pub fn encircles_pole<V: IndexCoordinate>(self, ring: &LineString<V>) -> bool {
+        match self {
+            CoordinateSystem::Planar => false,
+            CoordinateSystem::Geographic => encircles_pole(ring),
+        }
+    }
+}
+
+/// Behaviour of a lookup when the point lies outside of every polygon
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "mode", rename_all = "snake_case")
+)]
+pub enum Fallback<V> {
+    /// Use the default value
+    #[default]
+    Default,
+    /// Use the value of the nearest polygon, within an optional distance,
+    /// falling back to the default value otherwise
+    Nearest {
+        #[cfg_attr(feature = "serde", serde(default))]
+        max_distance: Option<V>,
+    },
+}
+
+/// Why a lookup selected the value it returned
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(tag = "reason", rename_all = "snake_case")
+)]
+pub enum LookupReason<V> {
+    /// No point was given, the default value is used
+    NoLocation,
+    /// A polygon contains the point, after `skipped` matching values were rejected by the filter
+    Area { skipped: usize },
+    /// No accepted polygon contains the point, the nearest one lies within `distance`
+    Nearest { skipped: usize, distance: V },
+    /// No accepted polygon contains the point (or is near enough), the default value is used
+    Outside { skipped: usize },
+}
+
+/// Intersection of polygons of two different values
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overlap<'a, T, V: CoordinateType = f32> {
+    /// Value of the polygon declared first, along with the index of the polygon among its polygons
+    pub first: (&'a T, usize),
+    /// Value of the polygon declared second, along with the index of the polygon among its polygons
+    pub second: (&'a T, usize),
+    /// Area of the intersection
+    pub area: V,
+    /// Bounding box of the intersection
+    pub bounds: Rect<V>,
+}
+
+/// Parts of a region not covered by any polygon of the index
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coverage<V: CoordinateType = f32> {
+    /// Uncovered parts of the region
+    pub uncovered: MultiPolygon<V>,
+    /// Area of the uncovered parts
+    pub uncovered_area: V,
+    /// Area of the whole region
+    pub region_area: V,
+}
+
+impl<V: IndexCoordinate> Coverage<V> {
+    /// Uncovered share of the region, in percent
+    pub fn uncovered_percentage(&self) -> V {
+        if self.region_area > V::zero() {
+            self.uncovered_area / self.region_area * V::from(100).unwrap()
+        } else {
+            V::zero()
+        }
+    }
+}
 
 /// Marker trait for index coordinate values
 pub trait IndexCoordinate: CoordinateType + Bounded + Signed + Float + Debug {}
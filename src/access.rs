@@ -0,0 +1,695 @@
+use crate::error::*;
+use log::*;
+
+use chrono::{DateTime, Local};
+use failure::format_err;
+use futures::{Async, Future, Poll, Stream};
+use geo_types::Point;
+use hyper::{
+    header::{HeaderMap, REFERER, USER_AGENT},
+    Body, Chunk, Error as HyperError, Method, Request, StatusCode, Uri, Version,
+};
+use serde::Serializer;
+use serde_derive::Serialize;
+use serde_json::json;
+use std::fmt::{self, Display, Write};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_signal::unix::{Signal, SIGUSR1};
+
+use crate::logfile::LogWriter;
+use crate::logger::{json_timestamp, LogFormat, ACCESS_TARGET};
+
+// templates of the predefined access log formats
+static COMMON_TEMPLATE: &str = "{client} - - [{time}] \"{request}\" {status} {bytes}";
+static COMBINED_TEMPLATE: &str =
+    "{client} - - [{time}] \"{request}\" {status} {bytes} \"{referer}\" \"{user_agent}\"";
+
+fn serialize_location<S>(
+    location: &Option<Point<f64>>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match location {
+        Some(point) => serializer.collect_seq([point.x(), point.y()]),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn serialize_display<S>(value: &impl Display, serializer: S) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_str(value)
+}
+
+fn serialize_version<S>(version: &Version, serializer: S) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_str(&format_args!("{:?}", version))
+}
+
+fn serialize_millis<S>(elapsed: &Duration, serializer: S) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_f64(elapsed.as_secs_f64() * 1000.0)
+}
+
+fn header(headers: &HeaderMap, name: impl hyper::header::AsHeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+/// Access log record of a single request
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct AccessRecord {
+    #[serde(skip)]
+    span: Instant,
+    /// Time the request was received
+    #[serde(skip)]
+    received: DateTime<Local>,
+    /// Address of the connected client
+    client: IpAddr,
+    #[serde(serialize_with = "serialize_display")]
+    method: Method,
+    /// URI requested by the client
+    uri: String,
+    #[serde(serialize_with = "serialize_version")]
+    version: Version,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    /// Upstream the request was sent to
+    upstream: Option<String>,
+    /// URI of the request sent to the upstream
+    upstream_uri: Option<String>,
+    backend: Option<String>,
+    #[serde(serialize_with = "serialize_location")]
+    location: Option<Point<f64>>,
+    status: u16,
+    #[serde(rename = "latency_ms", serialize_with = "serialize_millis")]
+    elapsed: Duration,
+    /// Response body bytes sent to the client
+    bytes: u64,
+    /// Reason of responses generated by the proxy
+    error: Option<&'static str>,
+}
+
+impl AccessRecord {
+    pub(crate) fn new<B>(
+        req: &Request<B>,
+        client: IpAddr,
+        location: Option<Point<f64>>,
+        span: Instant,
+    ) -> Self {
+        Self {
+            span,
+            received: Local::now(),
+            client,
+            method: req.method().clone(),
+            uri: req.uri().to_string(),
+            version: req.version(),
+            referer: header(req.headers(), REFERER),
+            user_agent: header(req.headers(), USER_AGENT),
+            upstream: None,
+            upstream_uri: None,
+            backend: None,
+            location,
+            status: 0,
+            elapsed: Duration::default(),
+            bytes: 0,
+            error: None,
+        }
+    }
+
+    pub(crate) fn backend(mut self, backend: String) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    pub(crate) fn upstream(mut self, upstream: String, uri: &Uri) -> Self {
+        self.upstream = Some(upstream);
+        self.upstream_uri = Some(uri.to_string());
+        self
+    }
+
+    /// Complete the record with the response sent to the client
+    pub(crate) fn response(mut self, status: StatusCode) -> Self {
+        self.status = status.as_u16();
+        self.elapsed = self.span.elapsed();
+        self
+    }
+
+    pub(crate) fn error(mut self, error: &'static str) -> Self {
+        self.error = Some(error);
+        self
+    }
+
+    pub(crate) fn method(&self) -> &Method {
+        &self.method
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub(crate) fn upstream_name(&self) -> &str {
+        self.upstream.as_deref().unwrap_or_default()
+    }
+
+    pub(crate) fn backend_label(&self) -> &str {
+        self.backend.as_deref().unwrap_or("none")
+    }
+
+    fn level(&self) -> Level {
+        if self.error.is_some() {
+            Level::Error
+        } else {
+            Level::Info
+        }
+    }
+
+    /// JSON object of the record, along with the given fields
+    fn json(&self, mut line: serde_json::Value) -> serde_json::Value {
+        if let (Some(line), Ok(serde_json::Value::Object(fields))) =
+            (line.as_object_mut(), serde_json::to_value(self))
+        {
+            line.extend(fields);
+        }
+
+        line
+    }
+
+    fn write_field(&self, out: &mut String, field: Field) -> fmt::Result {
+        // missing values are written as a dash, as in the common log format
+        fn or_dash(value: Option<&str>) -> &str {
+            value.unwrap_or("-")
+        }
+
+        match field {
+            Field::Client => write!(out, "{}", self.client),
+            Field::Time => write!(out, "{}", self.received.format("%d/%b/%Y:%H:%M:%S %z")),
+            Field::Request => write!(
+                out,
+                "{} {} {:?}",
+                self.method,
+                escape(&self.uri),
+                self.version
+            ),
+            Field::Method => write!(out, "{}", self.method),
+            Field::Uri => write!(out, "{}", escape(&self.uri)),
+            Field::Version => write!(out, "{:?}", self.version),
+            Field::Status => write!(out, "{}", self.status),
+            Field::Bytes => match self.bytes {
+                0 => write!(out, "-"),
+                bytes => write!(out, "{}", bytes),
+            },
+            Field::Referer => write!(out, "{}", escape(or_dash(self.referer.as_deref()))),
+            Field::UserAgent => write!(out, "{}", escape(or_dash(self.user_agent.as_deref()))),
+            Field::Backend => write!(out, "{}", or_dash(self.backend.as_deref())),
+            Field::Upstream => write!(out, "{}", or_dash(self.upstream.as_deref())),
+            Field::UpstreamUri => write!(out, "{}", escape(or_dash(self.upstream_uri.as_deref()))),
+            Field::Location => match self.location {
+                Some(point) => write!(out, "{},{}", point.x(), point.y()),
+                None => write!(out, "-"),
+            },
+            Field::Latency => write!(out, "{:.3}", self.elapsed.as_secs_f64() * 1000.0),
+            Field::Error => write!(out, "{}", or_dash(self.error)),
+        }
+    }
+}
+
+impl Display for AccessRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.upstream, self.error) {
+            (Some(upstream), None) => write!(
+                f,
+                "{} {} {} [via: {}, loc: {:?}] {:?}",
+                self.status, self.method, self.uri, upstream, self.location, self.elapsed
+            ),
+            _ => write!(
+                f,
+                "{} {} {} {:?}",
+                self.status, self.method, self.uri, self.elapsed
+            ),
+        }
+    }
+}
+
+/// Escape quotes, backslashes and control characters of values written to access logs
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02X}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Record field available in access log templates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Field {
+    Client,
+    Time,
+    Request,
+    Method,
+    Uri,
+    Version,
+    Status,
+    Bytes,
+    Referer,
+    UserAgent,
+    Backend,
+    Upstream,
+    UpstreamUri,
+    Location,
+    Latency,
+    Error,
+}
+
+impl FromStr for Field {
+    type Err = failure::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        Ok(match value {
+            "client" => Field::Client,
+            "time" => Field::Time,
+            "request" => Field::Request,
+            "method" => Field::Method,
+            "uri" => Field::Uri,
+            "version" => Field::Version,
+            "status" => Field::Status,
+            "bytes" => Field::Bytes,
+            "referer" => Field::Referer,
+            "user_agent" => Field::UserAgent,
+            "backend" => Field::Backend,
+            "upstream" => Field::Upstream,
+            "upstream_uri" => Field::UpstreamUri,
+            "location" => Field::Location,
+            "latency_ms" => Field::Latency,
+            "error" => Field::Error,
+            _ => return Err(format_err!("Unknown access log field {{{}}}", value)),
+        })
+    }
+}
+
+/// Part of a parsed access log template
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Segment {
+    Literal(String),
+    Field(Field),
+}
+
+/// Format of access log files
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum AccessFormat {
+    /// A JSON object per line
+    Json,
+    /// Line with fields substituted for their `{name}` placeholders
+    Template(Vec<Segment>),
+}
+
+impl AccessFormat {
+    fn template(template: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| format_err!("Unclosed field in access log format {}", template))?;
+
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_owned()));
+            }
+
+            segments.push(Segment::Field(rest[start + 1..end].parse()?));
+            rest = &rest[end + 1..];
+        }
+
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_owned()));
+        }
+
+        Ok(AccessFormat::Template(segments))
+    }
+
+    fn format(&self, record: &AccessRecord) -> String {
+        match self {
+            AccessFormat::Json => record
+                .json(json!({ "timestamp": json_timestamp() }))
+                .to_string(),
+            AccessFormat::Template(segments) => {
+                let mut line = String::new();
+
+                for segment in segments {
+                    let _ = match segment {
+                        Segment::Literal(literal) => line.write_str(literal),
+                        Segment::Field(field) => record.write_field(&mut line, *field),
+                    };
+                }
+
+                line
+            }
+        }
+    }
+}
+
+impl FromStr for AccessFormat {
+    type Err = failure::Error;
+
+    /// `common`, `combined`, `json` or a custom template
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "common" => Self::template(COMMON_TEMPLATE),
+            "combined" => Self::template(COMBINED_TEMPLATE),
+            "json" => Ok(AccessFormat::Json),
+            template => Self::template(template),
+        }
+    }
+}
+
+/// Destination of access log records
+enum AccessSink {
+    /// Diagnostic log, in its format
+    Log(LogFormat),
+    /// Separate log file
+    File {
+        writer: LogWriter,
+        format: AccessFormat,
+    },
+}
+
+/// Writes records of the requests handled by the proxy
+pub(crate) struct AccessLog {
+    sink: AccessSink,
+}
+
+impl AccessLog {
+    /// Access log written to the diagnostic log
+    pub(crate) fn new(format: LogFormat) -> Self {
+        Self {
+            sink: AccessSink::Log(format),
+        }
+    }
+
+    /// Access log written to a file
+    pub(crate) fn to_file(writer: LogWriter, format: AccessFormat) -> Self {
+        Self {
+            sink: AccessSink::File { writer, format },
+        }
+    }
+
+    pub(crate) fn log(&self, record: &AccessRecord) {
+        let level = record.level();
+
+        match &self.sink {
+            AccessSink::Log(LogFormat::Text) => log!(level, "{}", record),
+            AccessSink::Log(LogFormat::Json) => {
+                let line = record.json(json!({
+                    "timestamp": json_timestamp(),
+                    "level": level.as_str(),
+                    "target": ACCESS_TARGET,
+                }));
+
+                log!(target: ACCESS_TARGET, level, "{}", line);
+            }
+            AccessSink::File { writer, format } => writer.write(format.format(record)),
+        }
+    }
+
+    /// Response body logging the record once the body is sent, along with its size
+    pub(crate) fn log_body(self: Arc<Self>, record: AccessRecord, body: Body) -> Body {
+        Body::wrap_stream(LoggedBody {
+            body,
+            record: Some(record),
+            access_log: self,
+        })
+    }
+
+    /// Wait for the records logged so far to be written to the file
+    pub(crate) fn flush(&self) {
+        if let AccessSink::File { writer, .. } = &self.sink {
+            writer.flush();
+        }
+    }
+
+    /// Future reopening the access log file on SIGUSR1, e.g. after it was moved by logrotate
+    pub(crate) fn watch(self: Arc<Self>) -> Option<impl Future<Item = (), Error = ()>> {
+        if let AccessSink::Log(_) = self.sink {
+            return None;
+        }
+
+        let signals = Signal::new(SIGUSR1)
+            .flatten_stream()
+            .map_err(|error| error!("Unable to handle SIGUSR1: {}", error));
+
+        Some(signals.for_each(move |_| {
+            if let AccessSink::File { writer, .. } = &self.sink {
+                writer.reopen();
+            }
+
+            Ok(())
+        }))
+    }
+}
+
+/// Body counting the bytes sent, the record is logged when the body ends,
+/// fails or is dropped because the client went away
+struct LoggedBody {
+    body: Body,
+    record: Option<AccessRecord>,
+    access_log: Arc<AccessLog>,
+}
+
+impl LoggedBody {
+    fn finish(&mut self, error: Option<&'static str>) {
+        if let Some(mut record) = self.record.take() {
+            record.error = error.or(record.error);
+            self.access_log.log(&record);
+        }
+    }
+}
+
+impl Stream for LoggedBody {
+    type Item = Chunk;
+    type Error = HyperError;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, HyperError> {
+        match self.body.poll() {
+            Ok(Async::Ready(Some(chunk))) => {
+                if let Some(record) = &mut self.record {
+                    record.bytes += chunk.len() as u64;
+                }
+
+                Ok(Async::Ready(Some(chunk)))
+            }
+            Ok(Async::Ready(None)) => {
+                self.finish(None);
+                Ok(Async::Ready(None))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(error) => {
+                self.finish(Some("body_failed"));
+                Err(error)
+            }
+        }
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        self.finish(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use std::fs;
+    use std::io;
+    use std::process::{self, Command};
+    use std::thread;
+    use tokio::runtime::Runtime;
+
+    use crate::logfile::{LogFile, RotationPolicy};
+    use crate::testing::temp_dir;
+
+    fn record() -> AccessRecord {
+        let req = Request::builder()
+            .uri("/path?geo=1,2")
+            .header("User-Agent", "curl/7.64 \"test\"")
+            .body(())
+            .unwrap();
+
+        AccessRecord::new(
+            &req,
+            "10.0.0.1".parse().unwrap(),
+            Some(Point::new(2.0, 1.0)),
+            Instant::now(),
+        )
+        .backend("europe".to_owned())
+        .upstream(
+            "http://10.0.0.1/".to_owned(),
+            &"http://10.0.0.1/path?geo=1,2".parse().unwrap(),
+        )
+        .response(StatusCode::OK)
+    }
+
+    /// Access log written to a file in the format, along with the path of the file
+    fn file_log(dir: &tempfile::TempDir, format: &str) -> (Arc<AccessLog>, std::path::PathBuf) {
+        let path = dir.path().join("access.log");
+        let file = LogFile::open(&path, RotationPolicy::default()).unwrap();
+        let writer = LogWriter::spawn(file, "access-log-test").unwrap();
+
+        (
+            Arc::new(AccessLog::to_file(writer, format.parse().unwrap())),
+            path,
+        )
+    }
+
+    /// Line logged for the record once a body of the chunks is sent
+    fn logged(format: &str, record: AccessRecord, chunks: Vec<&'static str>) -> String {
+        let dir = temp_dir();
+        let (access_log, path) = file_log(&dir, format);
+        let body = Body::wrap_stream(stream::iter_ok::<_, io::Error>(chunks));
+
+        access_log
+            .clone()
+            .log_body(record, body)
+            .concat2()
+            .wait()
+            .unwrap();
+        access_log.flush();
+
+        fs::read_to_string(&path).unwrap().trim_end().to_owned()
+    }
+
+    #[test]
+    fn json() {
+        let line = logged("json", record(), vec!["hello", " world"]);
+        let value = serde_json::from_str::<serde_json::Value>(&line).unwrap();
+
+        assert_eq!(value["client"], "10.0.0.1");
+        assert_eq!(value["method"], "GET");
+        assert_eq!(value["uri"], "/path?geo=1,2");
+        assert_eq!(value["version"], "HTTP/1.1");
+        assert_eq!(value["upstream_uri"], "http://10.0.0.1/path?geo=1,2");
+        assert_eq!(value["backend"], "europe");
+        assert_eq!(value["location"], json!([2.0, 1.0]));
+        assert_eq!(value["status"], 200);
+        assert_eq!(value["bytes"], 11);
+        assert_eq!(value["error"], serde_json::Value::Null);
+        assert!(value["latency_ms"].is_f64());
+    }
+
+    #[test]
+    fn combined() {
+        let record = record();
+        let time = record.received.format("%d/%b/%Y:%H:%M:%S %z");
+        let line = logged("combined", record, vec!["hello", " world"]);
+
+        assert_eq!(
+            line,
+            format!(
+                "10.0.0.1 - - [{}] \"GET /path?geo=1,2 HTTP/1.1\" 200 11 \"-\" \"curl/7.64 \\\"test\\\"\"",
+                time
+            )
+        );
+    }
+
+    #[test]
+    fn template() {
+        let format = "{backend} {location} {status}!"
+            .parse::<AccessFormat>()
+            .unwrap();
+
+        assert_eq!(format.format(&record()), "europe 2,1 200!");
+        assert!("{unknown}".parse::<AccessFormat>().is_err());
+        assert!("{status".parse::<AccessFormat>().is_err());
+    }
+
+    #[test]
+    fn unfinished_body() {
+        let dir = temp_dir();
+        let (access_log, path) = file_log(&dir, "{status} {bytes} {error}");
+
+        // the client goes away after the first chunk
+        let body = Body::wrap_stream(stream::iter_ok::<_, io::Error>(vec!["partial", "rest"]));
+        let (_, body) = access_log
+            .clone()
+            .log_body(record(), body)
+            .into_future()
+            .wait()
+            .map_err(|(error, _)| error)
+            .unwrap();
+        drop(body);
+
+        // the upstream fails after the first chunk
+        let body = Body::wrap_stream(stream::iter_result(vec![
+            Ok("partial"),
+            Err(io::Error::other("connection reset")),
+        ]));
+        assert!(access_log
+            .clone()
+            .log_body(record(), body)
+            .concat2()
+            .wait()
+            .is_err());
+
+        access_log.flush();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "200 7 -\n200 7 body_failed\n"
+        );
+    }
+
+    #[test]
+    fn reopen_on_sigusr1() {
+        let dir = temp_dir();
+        let (access_log, path) = file_log(&dir, "{status} {bytes}");
+        let moved = dir.path().join("access.log.1");
+        let mut runtime = Runtime::new().unwrap();
+
+        // a handler has to be in place before signals are sent, SIGUSR1 terminates the process otherwise
+        runtime.block_on(Signal::new(SIGUSR1)).unwrap();
+        runtime.spawn(access_log.clone().watch().unwrap());
+
+        access_log.log(&record());
+        access_log.flush();
+        fs::rename(&path, &moved).unwrap();
+
+        // the watcher may not be listening yet, the signal is repeated until the file is reopened
+        let reopened = (0..100).any(|_| {
+            Command::new("kill")
+                .args(["-USR1", &process::id().to_string()])
+                .status()
+                .unwrap();
+            thread::sleep(Duration::from_millis(20));
+            access_log.flush();
+
+            path.exists()
+        });
+        assert!(reopened);
+
+        access_log.log(&record());
+        access_log.flush();
+        assert_eq!(fs::read_to_string(&moved).unwrap(), "200 -\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "200 -\n");
+    }
+}
@@ -0,0 +1,185 @@
+use futures::future;
+use geo_types::Point;
+use geoindex::LookupReason;
+use hyper::{header::CONTENT_TYPE, Body, Method, Request, Response, StatusCode};
+use prometheus::TEXT_FORMAT;
+use serde_derive::Serialize;
+use std::sync::Arc;
+use url::form_urlencoded;
+
+use crate::config::Backend;
+use crate::metrics::MetricsClient;
+use crate::proxy::ResponseFuture;
+use crate::shutdown::Shutdown;
+use crate::state::SharedState;
+
+/// Routing decision reported by the `/lookup` endpoint
+#[derive(Debug, Serialize)]
+struct Lookup<'a> {
+    location: Option<[f64; 2]>,
+    backend: &'a Backend,
+    default: bool,
+    healthy: bool,
+    #[serde(flatten)]
+    reason: LookupReason<f64>,
+}
+
+/// Point given with the `x` and `y` query parameters, none if both are missing
+fn query_point(query: Option<&str>) -> Result<Option<Point<f64>>, String> {
+    let mut x = None;
+    let mut y = None;
+
+    for (name, value) in form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+        let coord = match name.as_ref() {
+            "x" => &mut x,
+            "y" => &mut y,
+            _ => continue,
+        };
+
+        *coord = Some(
+            value
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .ok_or_else(|| format!("invalid {} coordinate {}", name, value))?,
+        );
+    }
+
+    match (x, y) {
+        (Some(x), Some(y)) => Ok(Some(Point::new(x, y))),
+        (None, None) => Ok(None),
+        _ => Err("both x and y coordinates are required".to_owned()),
+    }
+}
+
+fn text(status: StatusCode, body: impl Into<Body>) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain")
+        .body(body.into())
+        .unwrap()
+}
+
+fn json(value: &impl serde::Serialize) -> Response<Body> {
+    match serde_json::to_vec_pretty(value) {
+        Ok(body) => Response::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.into())
+            .unwrap(),
+        Err(error) => text(StatusCode::INTERNAL_SERVER_ERROR, error.to_string()),
+    }
+}
+
+/// Endpoints exposing the routing state, served on a separate listener
+pub(crate) struct Admin {
+    state: SharedState,
+    metrics: MetricsClient,
+    shutdown: Arc<Shutdown>,
+}
+
+impl Admin {
+    pub(crate) fn new(state: SharedState, metrics: MetricsClient, shutdown: Arc<Shutdown>) -> Self {
+        Self {
+            state,
+            metrics,
+            shutdown,
+        }
+    }
+
+    pub(crate) fn handle(&self, req: Request<Body>) -> ResponseFuture {
+        let resp = match (req.method(), req.uri().path()) {
+            (&Method::GET, "/health") | (&Method::HEAD, "/health") => text(StatusCode::OK, "OK"),
+            (&Method::GET, "/ready") | (&Method::HEAD, "/ready") => self.ready(),
+            (&Method::GET, "/config") => json(&self.state.load().config),
+            (&Method::GET, "/lookup") => self.lookup(req.uri().query()),
+            (&Method::GET, "/metrics") => self.metrics(),
+            (_, "/health") | (_, "/ready") | (_, "/config") | (_, "/lookup") | (_, "/metrics") => {
+                text(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed")
+            }
+            _ => text(StatusCode::NOT_FOUND, "Not found"),
+        };
+
+        Box::new(future::ok(resp))
+    }
+
+    /// Ready as long as any backend can take requests, until the proxy starts shutting down
+    fn ready(&self) -> Response<Body> {
+        let state = self.state.load();
+        let index = &state.index;
+
+        if self.shutdown.is_draining() {
+            text(StatusCode::SERVICE_UNAVAILABLE, "Shutting down")
+        } else if index
+            .values()
+            .chain(Some(index.default()))
+            .any(Backend::is_healthy)
+        {
+            text(StatusCode::OK, "OK")
+        } else {
+            text(StatusCode::SERVICE_UNAVAILABLE, "No healthy backends")
+        }
+    }
+
+    /// Request metrics in the Prometheus text format
+    fn metrics(&self) -> Response<Body> {
+        match self.metrics.export() {
+            Ok(body) => Response::builder()
+                .header(CONTENT_TYPE, TEXT_FORMAT)
+                .body(body.into())
+                .unwrap(),
+            Err(error) => text(StatusCode::INTERNAL_SERVER_ERROR, error.to_string()),
+        }
+    }
+
+    /// Backend a request located at the queried point would be proxied to
+    fn lookup(&self, query: Option<&str>) -> Response<Body> {
+        let location = match query_point(query) {
+            Ok(location) => location,
+            Err(error) => return text(StatusCode::BAD_REQUEST, error),
+        };
+
+        let state = self.state.load();
+        let point = location.map(|point| state.axis_order.normalize(point));
+
+        if let Some(point) = point {
+            if !state.index.coordinate_system().is_valid(point.0) {
+                return text(
+                    StatusCode::BAD_REQUEST,
+                    "coordinates out of the longitude or latitude range",
+                );
+            }
+        }
+
+        let (backend, reason) = state.index.explain_by(point.as_ref(), Backend::is_healthy);
+
+        json(&Lookup {
+            location: location.map(|point| [point.x(), point.y()]),
+            backend,
+            default: std::ptr::eq(backend, state.index.default()),
+            healthy: backend.is_healthy(),
+            reason,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn point() {
+        assert_eq!(
+            query_point(Some("x=1.5&y=-2&other=3")),
+            Ok(Some(Point::new(1.5, -2.0)))
+        );
+        assert_eq!(query_point(None), Ok(None));
+        assert_eq!(query_point(Some("other=3")), Ok(None));
+    }
+
+    #[test]
+    fn invalid_point() {
+        assert!(query_point(Some("x=1")).is_err());
+        assert!(query_point(Some("x=1&y=north")).is_err());
+        assert!(query_point(Some("x=1&y=NaN")).is_err());
+    }
+}
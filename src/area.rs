@@ -0,0 +1,345 @@
+use geo::algorithm::contains::Contains;
+use geo::algorithm::intersects::Intersects;
+use geo::algorithm::orient::{Direction, Orient};
+use geo_types::{Line, LineString, Point, Polygon};
+use geoindex::CoordinateSystem;
+use std::fmt::{self, Display};
+
+/// Ring of a polygon
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Ring {
+    Exterior,
+    Interior(usize),
+}
+
+impl Display for Ring {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ring::Exterior => write!(f, "exterior ring"),
+            Ring::Interior(index) => write!(f, "interior ring #{}", index),
+        }
+    }
+}
+
+/// Reason a polygon can't be used as a backend area
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum AreaIssue {
+    /// Coordinates are NaN or infinite
+    NonFinite(Ring),
+    /// Longitude or latitude out of its range, in the geographic coordinate system
+    OutOfRange(Ring),
+    /// Ring goes all the way around a pole, in the geographic coordinate system
+    EncirclesPole(Ring),
+    /// Less than three distinct points
+    TooFewPoints(Ring),
+    /// Last point differs from the first one
+    NotClosed(Ring),
+    /// Edges of the ring cross or overlap
+    SelfIntersecting(Ring),
+    /// Interior ring reaches outside of the exterior ring
+    InteriorOutside(usize),
+}
+
+impl AreaIssue {
+    /// Whether the issue is fixed by `repair_area`
+    pub(crate) fn is_repairable(self) -> bool {
+        matches!(self, AreaIssue::NotClosed(_))
+    }
+}
+
+impl Display for AreaIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AreaIssue::NonFinite(ring) => write!(f, "{} has non-finite coordinates", ring),
+            AreaIssue::OutOfRange(ring) => {
+                write!(
+                    f,
+                    "{} has coordinates out of the longitude or latitude range",
+                    ring
+                )
+            }
+            AreaIssue::EncirclesPole(ring) => write!(f, "{} encircles a pole", ring),
+            AreaIssue::TooFewPoints(ring) => write!(f, "{} has fewer than 3 points", ring),
+            AreaIssue::NotClosed(ring) => write!(f, "{} is not closed", ring),
+            AreaIssue::SelfIntersecting(ring) => write!(f, "{} intersects itself", ring),
+            AreaIssue::InteriorOutside(index) => write!(
+                f,
+                "{} lies outside of the exterior ring",
+                Ring::Interior(*index)
+            ),
+        }
+    }
+}
+
+fn is_closed(ring: &LineString<f64>) -> bool {
+    ring.0.first() == ring.0.last()
+}
+
+/// Edges of the ring as if it was closed, without the zero-length ones
+fn edges(ring: &LineString<f64>) -> Vec<Line<f64>> {
+    let points = &ring.0;
+
+    points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .filter(|(start, end)| start != end)
+        .map(|(start, end)| Line::new(*start, *end))
+        .collect()
+}
+
+/// Whether an edge turns back onto the previous one
+fn folds_back(previous: &Line<f64>, next: &Line<f64>) -> bool {
+    let cross = previous.dx() * next.dy() - previous.dy() * next.dx();
+    let dot = previous.dx() * next.dx() + previous.dy() * next.dy();
+
+    cross == 0.0 && dot < 0.0
+}
+
+/// Whether any two edges cross or overlap, apart from the shared ends of adjacent edges
+fn self_intersects(edges: &[Line<f64>]) -> bool {
+    let count = edges.len();
+    let adjacent = |a: usize, b: usize| b == a + 1 || (a == 0 && b == count - 1);
+
+    // sweep over the edges ordered by their leftmost point,
+    // only edges overlapping on the x axis need to be compared
+    let mut order = (0..count).collect::<Vec<_>>();
+    order.sort_by(|a, b| {
+        let a = edges[*a].start.x.min(edges[*a].end.x);
+        let b = edges[*b].start.x.min(edges[*b].end.x);
+        a.partial_cmp(&b).unwrap()
+    });
+
+    order.iter().enumerate().any(|(position, &a)| {
+        let right = edges[a].start.x.max(edges[a].end.x);
+
+        order[position + 1..]
+            .iter()
+            .take_while(|&&b| edges[b].start.x.min(edges[b].end.x) <= right)
+            .any(|&b| {
+                let (first, second) = (a.min(b), a.max(b));
+
+                if adjacent(first, second) {
+                    let (previous, next) = if second == first + 1 {
+                        (first, second)
+                    } else {
+                        (second, first)
+                    };
+
+                    folds_back(&edges[previous], &edges[next])
+                } else {
+                    edges[a].intersects(&edges[b])
+                }
+            })
+    })
+}
+
+fn ring_issues(ring: &LineString<f64>, kind: Ring, system: CoordinateSystem) -> Vec<AreaIssue> {
+    if ring
+        .0
+        .iter()
+        .any(|point| !point.x.is_finite() || !point.y.is_finite())
+    {
+        return vec![AreaIssue::NonFinite(kind)];
+    }
+
+    if !ring.0.iter().all(|point| system.is_valid(*point)) {
+        return vec![AreaIssue::OutOfRange(kind)];
+    }
+
+    let edges = edges(ring);
+
+    // a closed ring of n distinct points has n edges
+    if edges.len() < 3 {
+        return vec![AreaIssue::TooFewPoints(kind)];
+    }
+
+    let mut issues = Vec::new();
+
+    if !is_closed(ring) {
+        issues.push(AreaIssue::NotClosed(kind));
+    }
+
+    if self_intersects(&edges) {
+        issues.push(AreaIssue::SelfIntersecting(kind));
+    }
+
+    if system.encircles_pole(ring) {
+        issues.push(AreaIssue::EncirclesPole(kind));
+    }
+
+    issues
+}
+
+/// Whether every point of the interior ring lies within the exterior ring, or on it
+fn within_exterior(exterior: &Polygon<f64>, interior: &LineString<f64>) -> bool {
+    interior.0.iter().all(|coordinate| {
+        let point = Point(*coordinate);
+
+        exterior.contains(&point) || exterior.exterior().contains(&point)
+    })
+}
+
+/// Problems of the polygon making it route unpredictably
+pub(crate) fn area_issues(area: &Polygon<f64>, system: CoordinateSystem) -> Vec<AreaIssue> {
+    let mut issues = ring_issues(area.exterior(), Ring::Exterior, system);
+    let exterior_valid = issues.iter().all(|issue| issue.is_repairable());
+    let exterior = Polygon::new(area.exterior().clone(), Vec::new());
+
+    for (index, interior) in area.interiors().iter().enumerate() {
+        let interior_issues = ring_issues(interior, Ring::Interior(index), system);
+        let interior_valid = interior_issues.iter().all(|issue| issue.is_repairable());
+
+        issues.extend(interior_issues);
+
+        if exterior_valid && interior_valid && !within_exterior(&exterior, interior) {
+            issues.push(AreaIssue::InteriorOutside(index));
+        }
+    }
+
+    issues
+}
+
+/// Close the rings of the polygon and wind the exterior ring counter-clockwise,
+/// interior rings clockwise; returns none if the polygon didn't need repairs
+pub(crate) fn repair_area(area: &Polygon<f64>) -> Option<Polygon<f64>> {
+    let closed = Polygon::new(area.exterior().clone(), area.interiors().to_vec());
+    let repaired = closed.orient(Direction::Default);
+
+    if &repaired == area {
+        None
+    } else {
+        Some(repaired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo_types::Coordinate;
+
+    fn ring(points: &[(f64, f64)]) -> LineString<f64> {
+        points
+            .iter()
+            .map(|&(x, y)| Coordinate { x, y })
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    // as read from the config, `Polygon::new` would close the rings
+    fn polygon(exterior: &[(f64, f64)], interiors: &[&[(f64, f64)]]) -> Polygon<f64> {
+        serde_json::from_value(serde_json::json!({
+            "exterior": ring(exterior),
+            "interiors": interiors.iter().map(|interior| ring(interior)).collect::<Vec<_>>(),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn valid() {
+        let area = polygon(
+            &[(0., 0.), (10., 0.), (10., 10.), (0., 10.), (0., 0.)],
+            &[&[(2., 2.), (2., 4.), (4., 4.), (4., 2.), (2., 2.)]],
+        );
+
+        assert_eq!(area_issues(&area, CoordinateSystem::Planar), vec![]);
+        assert_eq!(repair_area(&area), None);
+    }
+
+    #[test]
+    fn invalid() {
+        let unclosed = polygon(&[(0., 0.), (10., 0.), (10., 10.), (0., 10.)], &[]);
+        assert_eq!(
+            area_issues(&unclosed, CoordinateSystem::Planar),
+            vec![AreaIssue::NotClosed(Ring::Exterior)]
+        );
+
+        let line = polygon(&[(0., 0.), (10., 0.), (0., 0.)], &[]);
+        assert_eq!(
+            area_issues(&line, CoordinateSystem::Planar),
+            vec![AreaIssue::TooFewPoints(Ring::Exterior)]
+        );
+
+        let bowtie = polygon(&[(0., 0.), (10., 10.), (10., 0.), (0., 10.), (0., 0.)], &[]);
+        assert_eq!(
+            area_issues(&bowtie, CoordinateSystem::Planar),
+            vec![AreaIssue::SelfIntersecting(Ring::Exterior)]
+        );
+
+        let spike = polygon(
+            &[
+                (0., 0.),
+                (10., 0.),
+                (15., 0.),
+                (10., 0.),
+                (10., 10.),
+                (0., 0.),
+            ],
+            &[],
+        );
+        assert_eq!(
+            area_issues(&spike, CoordinateSystem::Planar),
+            vec![AreaIssue::SelfIntersecting(Ring::Exterior)]
+        );
+
+        let hole_outside = polygon(
+            &[(0., 0.), (10., 0.), (10., 10.), (0., 10.), (0., 0.)],
+            &[&[(8., 8.), (8., 12.), (12., 12.), (12., 8.), (8., 8.)]],
+        );
+        assert_eq!(
+            area_issues(&hole_outside, CoordinateSystem::Planar),
+            vec![AreaIssue::InteriorOutside(0)]
+        );
+    }
+
+    #[test]
+    fn geographic() {
+        let system = CoordinateSystem::Geographic;
+
+        let antimeridian = polygon(
+            &[
+                (170., 0.),
+                (-170., 0.),
+                (-170., 10.),
+                (170., 10.),
+                (170., 0.),
+            ],
+            &[],
+        );
+        assert_eq!(area_issues(&antimeridian, system), vec![]);
+
+        let out_of_range = polygon(
+            &[(170., 0.), (190., 0.), (190., 10.), (170., 10.), (170., 0.)],
+            &[],
+        );
+        assert_eq!(area_issues(&out_of_range, CoordinateSystem::Planar), vec![]);
+        assert_eq!(
+            area_issues(&out_of_range, system),
+            vec![AreaIssue::OutOfRange(Ring::Exterior)]
+        );
+
+        let polar = polygon(&[(0., 80.), (120., 82.), (-120., 84.), (0., 80.)], &[]);
+        assert_eq!(
+            area_issues(&polar, system),
+            vec![AreaIssue::EncirclesPole(Ring::Exterior)]
+        );
+    }
+
+    #[test]
+    fn repair() {
+        let area = polygon(
+            &[(0., 0.), (0., 10.), (10., 10.), (10., 0.)],
+            &[&[(2., 2.), (4., 2.), (4., 4.), (2., 4.)]],
+        );
+        let repaired = repair_area(&area).unwrap();
+
+        assert_eq!(area_issues(&repaired, CoordinateSystem::Planar), vec![]);
+        assert_eq!(
+            repaired.exterior(),
+            &ring(&[(0., 0.), (10., 0.), (10., 10.), (0., 10.), (0., 0.)])
+        );
+        assert_eq!(
+            repaired.interiors()[0],
+            ring(&[(2., 2.), (2., 4.), (4., 4.), (4., 2.), (2., 2.)])
+        );
+    }
+}
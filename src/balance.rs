@@ -0,0 +1,296 @@
+use http::{header::HeaderMap, uri::Uri};
+use serde::de::{Deserialize, Deserializer};
+use serde_derive::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use url::Url;
+
+use crate::health::HealthState;
+
+/// Strategy used to pick an upstream of a backend for each request
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub(crate) enum Balance {
+    #[default]
+    RoundRobin,
+    LeastOutstanding,
+    /// Hash of the header value selects the upstream,
+    /// requests without the header are balanced round-robin
+    ConsistentHash {
+        header: String,
+    },
+}
+
+/// A single upstream server of a backend
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct Upstream {
+    #[serde(with = "url_serde")]
+    url: Url,
+    #[serde(skip)]
+    outstanding: Arc<AtomicUsize>,
+    #[serde(skip)]
+    health: Arc<HealthState>,
+}
+
+impl Upstream {
+    pub(crate) fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub(crate) fn health(&self) -> &Arc<HealthState> {
+        &self.health
+    }
+
+    pub(crate) fn is_healthy(&self) -> bool {
+        self.health.is_healthy()
+    }
+
+    pub(crate) fn outstanding(&self) -> usize {
+        self.outstanding.load(Ordering::Relaxed)
+    }
+
+    /// Track a request sent to this upstream, until the returned guard is dropped
+    pub(crate) fn start_request(&self) -> OutstandingRequest {
+        self.outstanding.fetch_add(1, Ordering::Relaxed);
+
+        OutstandingRequest(self.outstanding.clone())
+    }
+
+    pub(crate) fn health_url(&self, path: &str) -> Uri {
+        let mut url = self.url.clone();
+        url.set_path(path);
+
+        url.as_str().parse().unwrap()
+    }
+
+    pub(crate) fn map_url(&self, uri: &Uri) -> Uri {
+        let mut new = self.url.clone();
+
+        // clear preexisting settings
+        new.set_path(uri.path());
+        new.set_query(uri.query());
+
+        new.as_str().parse().unwrap()
+    }
+}
+
+impl Display for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.url)
+    }
+}
+
+pub(crate) struct OutstandingRequest(Arc<AtomicUsize>);
+
+impl Drop for OutstandingRequest {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Accept either a single upstream or a list of them
+pub(crate) fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<Upstream>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(Upstream),
+        Many(Vec<Upstream>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(upstream) => vec![upstream],
+        OneOrMany::Many(upstreams) => upstreams,
+    })
+}
+
+/// 64-bit FNV-1a hash, unlike the std hashers it is the same on every platform and release,
+/// so clients keep their upstream across upgrades
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Jump consistent hash (Lamping, Veach), maps a key to one of `buckets` buckets
+fn jump_hash(mut key: u64, buckets: usize) -> usize {
+    let mut b = -1i64;
+    let mut j = 0i64;
+
+    while j < buckets as i64 {
+        b = j;
+        key = key.wrapping_mul(2_862_933_555_777_941_757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+
+    b as usize
+}
+
+impl Balance {
+    /// Pick an upstream for the request, unhealthy upstreams are skipped
+    /// unless every one of them is unhealthy
+    pub(crate) fn select<'a>(
+        &self,
+        upstreams: &'a [Upstream],
+        next: &AtomicUsize,
+        headers: &HeaderMap,
+    ) -> &'a Upstream {
+        let healthy = upstreams.iter().any(Upstream::is_healthy);
+        let candidate = |upstream: &&Upstream| !healthy || upstream.is_healthy();
+
+        let start = match self {
+            Balance::LeastOutstanding => {
+                return upstreams
+                    .iter()
+                    .filter(candidate)
+                    .min_by_key(|upstream| upstream.outstanding())
+                    .unwrap_or(&upstreams[0]);
+            }
+            Balance::ConsistentHash { header } => headers
+                .get(header.as_str())
+                .map(|value| jump_hash(fnv1a(value.as_bytes()), upstreams.len()))
+                .unwrap_or_else(|| next.fetch_add(1, Ordering::Relaxed)),
+            Balance::RoundRobin => next.fetch_add(1, Ordering::Relaxed),
+        };
+
+        // walk from the selected upstream to the first available one
+        upstreams
+            .iter()
+            .cycle()
+            .skip(start % upstreams.len())
+            .take(upstreams.len())
+            .find(candidate)
+            .unwrap_or(&upstreams[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::header::HeaderValue;
+
+    fn upstreams(count: usize) -> Vec<Upstream> {
+        (0..count)
+            .map(|id| serde_json::from_value(format!("http://upstream{}", id).into()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn round_robin() {
+        let upstreams = upstreams(3);
+        let next = AtomicUsize::new(0);
+        let headers = HeaderMap::new();
+
+        let selected = (0..4)
+            .map(|_| {
+                Balance::RoundRobin
+                    .select(&upstreams, &next, &headers)
+                    .url()
+                    .as_str()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            selected,
+            vec![
+                "http://upstream0/",
+                "http://upstream1/",
+                "http://upstream2/",
+                "http://upstream0/"
+            ]
+        );
+    }
+
+    #[test]
+    fn least_outstanding() {
+        let upstreams = upstreams(3);
+        let next = AtomicUsize::new(0);
+        let headers = HeaderMap::new();
+
+        let _first = upstreams[0].start_request();
+        let _second = upstreams[1].start_request();
+
+        {
+            let _third = upstreams[2].start_request();
+            let _another = upstreams[2].start_request();
+
+            let selected = Balance::LeastOutstanding.select(&upstreams, &next, &headers);
+            assert_eq!(selected.url().as_str(), "http://upstream0/");
+        }
+
+        let selected = Balance::LeastOutstanding.select(&upstreams, &next, &headers);
+        assert_eq!(selected.url().as_str(), "http://upstream2/");
+    }
+
+    #[test]
+    fn consistent_hash() {
+        let upstreams = upstreams(5);
+        let next = AtomicUsize::new(0);
+        let balance = Balance::ConsistentHash {
+            header: "X-User".to_owned(),
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert("X-User", HeaderValue::from_static("user-1"));
+
+        let first = balance.select(&upstreams, &next, &headers).url().clone();
+
+        for _ in 0..10 {
+            assert_eq!(balance.select(&upstreams, &next, &headers).url(), &first);
+        }
+    }
+
+    #[test]
+    fn consistent_hash_mapping() {
+        let upstreams = upstreams(5);
+        let next = AtomicUsize::new(0);
+        let balance = Balance::ConsistentHash {
+            header: "X-User".to_owned(),
+        };
+
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+
+        // the mapping is part of the deployment, it must not change between builds
+        let selected = ["alice", "bob", "carol", "dave", "erin", "frank"]
+            .iter()
+            .map(|user| {
+                let mut headers = HeaderMap::new();
+                headers.insert("X-User", HeaderValue::from_static(user));
+
+                balance
+                    .select(&upstreams, &next, &headers)
+                    .url()
+                    .as_str()
+                    .to_owned()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            selected,
+            vec![
+                "http://upstream4/",
+                "http://upstream2/",
+                "http://upstream3/",
+                "http://upstream0/",
+                "http://upstream3/",
+                "http://upstream2/",
+            ]
+        );
+    }
+
+    #[test]
+    fn jump_hash_stability() {
+        // growing the number of buckets moves only keys to the new bucket
+        for key in 0..1000u64 {
+            let before = jump_hash(key, 10);
+            let after = jump_hash(key, 11);
+
+            assert!(after == before || after == 10);
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use crate::error::*;
+
+use failure::format_err;
+
+use crate::config::read_config;
+use crate::index::Precision;
+use crate::util::setup_index;
+
+/// Report areas of different backends overlapping each other,
+/// fails if any overlap is larger than `max_overlap`
+pub(crate) fn check_config(
+    path: &str,
+    precision: Option<Precision>,
+    max_overlap: f64,
+) -> Result<()> {
+    let index = setup_index(read_config(path, precision)?);
+    let overlaps = index.overlaps();
+
+    for overlap in &overlaps {
+        let (first, first_area) = overlap.first;
+        let (second, second_area) = overlap.second;
+
+        println!(
+            "{} area #{} overlaps {} area #{}: area {}, bounds ({}, {}) - ({}, {})",
+            first.label(),
+            first_area,
+            second.label(),
+            second_area,
+            overlap.area,
+            overlap.bounds.min.x,
+            overlap.bounds.min.y,
+            overlap.bounds.max.x,
+            overlap.bounds.max.y,
+        );
+    }
+
+    let exceeding = overlaps
+        .iter()
+        .filter(|overlap| overlap.area > max_overlap)
+        .count();
+
+    println!(
+        "{}: {} overlaps, {} larger than {}",
+        path,
+        overlaps.len(),
+        exceeding,
+        max_overlap
+    );
+
+    if exceeding > 0 {
+        Err(format_err!(
+            "{} overlaps are larger than {}",
+            exceeding,
+            max_overlap
+        ))
+    } else {
+        Ok(())
+    }
+}
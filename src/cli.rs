@@ -1,6 +1,13 @@
-use clap::{app_from_crate, crate_authors, crate_description, crate_name, crate_version, App, Arg};
+use clap::{
+    app_from_crate, crate_authors, crate_description, crate_name, crate_version, App, Arg,
+    SubCommand,
+};
+use ipnetwork::IpNetwork;
 use std::net::ToSocketAddrs;
 
+use crate::access::AccessFormat;
+use crate::logfile::parse_size;
+
 fn validate_sockaddr(value: String) -> Result<(), String> {
     value
         .to_socket_addrs()
@@ -8,9 +15,46 @@ fn validate_sockaddr(value: String) -> Result<(), String> {
         .map_err(|_| "invalid socket address".to_owned())
 }
 
+fn validate_network(value: String) -> Result<(), String> {
+    value
+        .parse::<IpNetwork>()
+        .map(|_| ())
+        .map_err(|_| "invalid IP address or network".to_owned())
+}
+
+fn validate_seconds(value: String) -> Result<(), String> {
+    value
+        .parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| "invalid number of seconds".to_owned())
+}
+
+fn validate_area(value: String) -> Result<(), String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|area| area.is_finite() && *area >= 0.0)
+        .map(|_| ())
+        .ok_or_else(|| "invalid area".to_owned())
+}
+
+fn validate_access_format(value: String) -> Result<(), String> {
+    value
+        .parse::<AccessFormat>()
+        .map(|_| ())
+        .map_err(|error| error.to_string())
+}
+
+fn validate_size(value: String) -> Result<(), String> {
+    parse_size(&value)
+        .map(|_| ())
+        .map_err(|error| error.to_string())
+}
+
 pub(super) fn setup_cli<'a, 'b>() -> App<'a, 'b> {
     const DEFAULT_SERVER_BIND: &str = "localhost:8000";
     const DEFAULT_CONFIG_NAME: &str = "config.json";
+    const DEFAULT_DRAIN_TIMEOUT: &str = "30";
 
     app_from_crate!()
         .arg(
@@ -23,6 +67,14 @@ pub(super) fn setup_cli<'a, 'b>() -> App<'a, 'b> {
                 .short("a")
                 .long("address"),
         )
+        .arg(
+            Arg::with_name("admin_address")
+                .takes_value(true)
+                .help("Address to serve the admin endpoints on (e.g. 'localhost:8001'), disabled by default")
+                .required(false)
+                .validator(validate_sockaddr)
+                .long("admin-address"),
+        )
         .arg(
             Arg::with_name("statsd")
                 .takes_value(true)
@@ -32,13 +84,141 @@ pub(super) fn setup_cli<'a, 'b>() -> App<'a, 'b> {
                 .short("s")
                 .long("statsd"),
         )
+        .arg(
+            Arg::with_name("metric_tags")
+                .takes_value(true)
+                .help("How statsd request metrics are broken down by backend, status class and method")
+                .required(false)
+                .possible_values(&["dotted", "dogstatsd"])
+                .default_value("dotted")
+                .long("metric-tags"),
+        )
+        .arg(
+            Arg::with_name("log_format")
+                .takes_value(true)
+                .help("Format of the log output, the json format writes a JSON object per line")
+                .required(false)
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .long("log-format"),
+        )
+        .arg(
+            Arg::with_name("access_log")
+                .takes_value(true)
+                .help("File to write the access log to, instead of the diagnostic log")
+                .required(false)
+                .long("access-log"),
+        )
+        .arg(
+            Arg::with_name("access_log_format")
+                .takes_value(true)
+                .help("Format of the access log file: common, combined, json or a template (e.g. '{client} {status} {backend}')")
+                .required(false)
+                .default_value("combined")
+                .validator(validate_access_format)
+                .long("access-log-format"),
+        )
+        .arg(
+            Arg::with_name("access_log_max_size")
+                .takes_value(true)
+                .help("Size the access log file is rotated at (e.g. '100M')")
+                .required(false)
+                .requires("access_log")
+                .validator(validate_size)
+                .long("access-log-max-size"),
+        )
+        .arg(
+            Arg::with_name("access_log_rotate")
+                .takes_value(true)
+                .help("Period the access log file is rotated after")
+                .required(false)
+                .requires("access_log")
+                .possible_values(&["hourly", "daily"])
+                .long("access-log-rotate"),
+        )
         .arg(
             Arg::with_name("config")
                 .takes_value(true)
                 .help("Location of the backend config file")
-                .required(true)
+                .required(false)
                 .default_value(DEFAULT_CONFIG_NAME)
+                .global(true)
                 .short("c")
                 .long("config"),
         )
+        .arg(
+            Arg::with_name("coordinate_precision")
+                .takes_value(true)
+                .help("Precision of the coordinates in the area index, overrides the coordinate_precision of the config")
+                .required(false)
+                .possible_values(&["f32", "f64"])
+                .global(true)
+                .long("coordinate-precision"),
+        )
+        .arg(
+            Arg::with_name("geoip_db")
+                .takes_value(true)
+                .help("MaxMind (.mmdb) database used to locate clients by IP, when the request provides no location")
+                .required(false)
+                .long("geoip-db"),
+        )
+        .arg(
+            Arg::with_name("trusted_proxy")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Address or network of a proxy trusted to provide the client address with X-Forwarded-For (e.g. '10.0.0.0/8')")
+                .required(false)
+                .validator(validate_network)
+                .long("trusted-proxy"),
+        )
+        .arg(
+            Arg::with_name("drain_timeout")
+                .takes_value(true)
+                .help("Seconds to wait for requests in flight to finish on SIGTERM or SIGINT")
+                .required(false)
+                .default_value(DEFAULT_DRAIN_TIMEOUT)
+                .validator(validate_seconds)
+                .long("drain-timeout"),
+        )
+        .arg(
+            Arg::with_name("tls_cert")
+                .takes_value(true)
+                .help("PEM certificate (chain) to serve HTTPS with, overrides the tls section of the config")
+                .required(false)
+                .requires("tls_key")
+                .long("tls-cert"),
+        )
+        .arg(
+            Arg::with_name("tls_key")
+                .takes_value(true)
+                .help("PEM private key of the --tls-cert certificate")
+                .required(false)
+                .requires("tls_cert")
+                .long("tls-key"),
+        )
+        .subcommand(
+            SubCommand::with_name("check-config")
+                .about("Validate the config and report areas of different backends overlapping each other")
+                .arg(
+                    Arg::with_name("max_overlap")
+                        .takes_value(true)
+                        .help("Largest overlap area allowed, in squared coordinate units")
+                        .required(false)
+                        .default_value("0")
+                        .validator(validate_area)
+                        .long("max-overlap"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("coverage")
+                .about("Print the parts of a region not covered by any backend area, as GeoJSON")
+                .arg(
+                    Arg::with_name("region")
+                        .takes_value(true)
+                        .help("GeoJSON file with the polygons of the region")
+                        .required(true)
+                        .long("region"),
+                ),
+        )
 }
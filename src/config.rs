@@ -1,64 +1,282 @@
+use crate::area::{area_issues, repair_area};
+use crate::balance::{one_or_many, Balance, Upstream};
+use crate::connector::{HttpsConnector, UpstreamClient, UpstreamTls};
 use crate::error::*;
+use crate::features::GeoJsonSource;
+use crate::headers::HeadersConfig;
+use crate::health::HealthCheck;
+use crate::index::Precision;
+use crate::location::{default_sources, AxisOrder, LocationSource};
+use crate::tls::TlsConfig;
 use failure::format_err;
 use geo_types::Polygon;
-use http::uri::Uri;
+use geoindex::{CoordinateSystem, Fallback, OverlapStrategy};
+use http::{
+    header::{HeaderMap, HeaderValue},
+    Method,
+};
+use log::warn;
 use serde_derive::{Deserialize, Serialize};
-use serde_json;
 use std::fmt::{self, Display};
 use std::fs::File;
 use std::path::Path;
+use std::sync::atomic::AtomicUsize;
+use std::time::Duration;
 use url::Url;
-use url_serde;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+mod methods_serde {
+    use http::Method;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S>(
+        methods: &Option<Vec<Method>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match methods {
+            Some(methods) => serializer.collect_seq(methods.iter().map(Method::as_str)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(super) fn deserialize<'de, D>(deserializer: D) -> Result<Option<Vec<Method>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<Vec<String>>::deserialize(deserializer)?
+            .map(|methods| {
+                methods
+                    .iter()
+                    .map(|method| {
+                        Method::from_bytes(method.to_uppercase().as_bytes()).map_err(|_| {
+                            D::Error::custom(format!("invalid HTTP method {}", method))
+                        })
+                    })
+                    .collect()
+            })
+            .transpose()
+    }
+}
+
+fn validate_url(url: &Url) -> Result<()> {
+    if url.cannot_be_a_base() || (url.scheme() != "http" && url.scheme() != "https") {
+        Err(format_err!("Backend URL scheme has to be http(s), {}", url))
+    } else if !url.has_host() {
+        Err(format_err!(
+            "Backend URL needs to have a host specified, {}",
+            url
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct Backend {
-    #[serde(with = "url_serde")]
-    base_url: Url,
+    /// Name of the backend, e.g. the region it serves
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    /// Upstream servers, `base_url` is accepted for backends with a single upstream
+    #[serde(alias = "base_url", deserialize_with = "one_or_many")]
+    upstreams: Vec<Upstream>,
+    #[serde(default)]
+    balance: Balance,
+    /// HTTP methods accepted by this backend, all methods are allowed if not specified
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "methods_serde"
+    )]
+    methods: Option<Vec<Method>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    health_check: Option<HealthCheck>,
+    /// TLS settings of `https` upstreams
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tls: Option<UpstreamTls>,
+    /// Time allowed to establish a connection to an upstream
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    connect_timeout_ms: Option<u64>,
+    /// Time allowed for an upstream to respond with the response headers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response_timeout_ms: Option<u64>,
+    #[serde(skip)]
+    client: Option<UpstreamClient>,
+    // round-robin position
+    #[serde(skip)]
+    next: AtomicUsize,
 }
 
 impl Backend {
-    pub(crate) fn map_url(&self, uri: &Uri) -> Uri {
-        let mut new = self.base_url.clone();
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Name of the backend, or its upstreams if the backend has no name
+    pub(crate) fn label(&self) -> String {
+        self.name()
+            .map(str::to_owned)
+            .unwrap_or_else(|| self.to_string())
+    }
+
+    pub(crate) fn allows_method(&self, method: &Method) -> bool {
+        self.methods
+            .as_ref()
+            .map(|methods| methods.contains(method))
+            .unwrap_or(true)
+    }
+
+    /// Value of the `Allow` header of responses rejecting other methods
+    pub(crate) fn allow_header(&self) -> Option<HeaderValue> {
+        let methods = self.methods.as_ref()?;
+        let allowed = methods
+            .iter()
+            .map(Method::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        HeaderValue::from_str(&allowed).ok()
+    }
+
+    /// Backend is healthy as long as any of its upstreams is
+    pub(crate) fn is_healthy(&self) -> bool {
+        self.upstreams.iter().any(Upstream::is_healthy)
+    }
 
-        // clear preexisting settings
-        new.set_path(uri.path());
-        new.set_query(uri.query());
+    pub(crate) fn health_check(&self) -> Option<&HealthCheck> {
+        self.health_check.as_ref()
+    }
+
+    pub(crate) fn upstreams(&self) -> &[Upstream] {
+        &self.upstreams
+    }
 
-        new.as_str().parse().unwrap()
+    pub(crate) fn client(&self) -> &UpstreamClient {
+        self.client
+            .as_ref()
+            .expect("backend client is set up when reading the config")
+    }
+
+    pub(crate) fn response_timeout(&self) -> Option<Duration> {
+        self.response_timeout_ms.map(Duration::from_millis)
+    }
+
+    /// Build the client used to reach the upstreams,
+    /// relative paths of the TLS settings are resolved against `base_dir`
+    fn setup_client(&mut self, base_dir: &Path) -> Result<()> {
+        if let Some(tls) = &mut self.tls {
+            tls.resolve_paths(base_dir);
+        }
+
+        let connect_timeout = self.connect_timeout_ms.map(Duration::from_millis);
+        let client = HttpsConnector::client(&self.tls.clone().unwrap_or_default(), connect_timeout)
+            .map_err(|error| {
+                format_err!(
+                    "Unable to set up TLS of backend {}: {}",
+                    self.label(),
+                    error
+                )
+            })?;
+        self.client = Some(client);
+
+        Ok(())
+    }
+
+    /// Upstream to handle the request, according to the balancing strategy
+    pub(crate) fn select_upstream(&self, headers: &HeaderMap) -> &Upstream {
+        self.balance.select(&self.upstreams, &self.next, headers)
     }
 
     fn validate(&self) -> Result<()> {
-        if self.base_url.cannot_be_a_base()
-            || (self.base_url.scheme() != "http" && self.base_url.scheme() != "https")
+        if self.upstreams.is_empty() {
+            Err(format_err!(
+                "Backend {} needs at least one upstream URL",
+                self.name().unwrap_or("<unnamed>")
+            ))
+        } else if self
+            .methods
+            .as_ref()
+            .map(|methods| methods.is_empty())
+            .unwrap_or(false)
+        {
+            Err(format_err!("Backend method list cannot be empty, {}", self))
+        } else if self
+            .health_check
+            .as_ref()
+            .map(|check| !check.is_valid())
+            .unwrap_or(false)
         {
             Err(format_err!(
-                "Backend URL scheme has to be http(s), {}",
-                self.base_url
+                "Backend health check needs an absolute path and non-zero interval, timeout and thresholds, {}",
+                self
             ))
-        } else if !self.base_url.has_host() {
+        } else if self.connect_timeout_ms == Some(0) || self.response_timeout_ms == Some(0) {
             Err(format_err!(
-                "Backend URL needs to have a host specified, {}",
-                self.base_url
+                "Backend timeouts have to be non-zero, {}",
+                self
             ))
         } else {
-            Ok(())
+            self.upstreams
+                .iter()
+                .try_for_each(|upstream| validate_url(upstream.url()))
         }
     }
 }
 
 impl Display for Backend {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.base_url)
+        for (id, upstream) in self.upstreams.iter().enumerate() {
+            if id > 0 {
+                write!(f, ", ")?;
+            }
+
+            write!(f, "{}", upstream)?;
+        }
+
+        Ok(())
     }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct BackendDefinition {
-    pub(crate) areas: Vec<Polygon<f32>>,
+    pub(crate) areas: Vec<Polygon<f64>>,
     pub(crate) backend: Backend,
+    /// Priority used when resolving overlapping areas with the `highest_priority` strategy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) priority: Option<i32>,
 }
 
 impl BackendDefinition {
+    fn label(&self) -> String {
+        self.backend.label()
+    }
+
+    /// Problems of every invalid area, along with the backend and index of the area
+    fn area_issues(&self, system: CoordinateSystem) -> Vec<String> {
+        self.areas
+            .iter()
+            .enumerate()
+            .flat_map(|(index, area)| {
+                area_issues(area, system).into_iter().map(move |issue| {
+                    format!("backend {}, area #{}: {}", self.label(), index, issue)
+                })
+            })
+            .collect()
+    }
+
+    /// Close the rings of the areas and fix their winding
+    fn repair_areas(&mut self) {
+        let label = self.label();
+
+        for (index, area) in self.areas.iter_mut().enumerate() {
+            if let Some(repaired) = repair_area(area) {
+                warn!("Repaired backend {}, area #{}", label, index);
+                *area = repaired;
+            }
+        }
+    }
+
     fn validate(&self) -> Result<()> {
         if self.areas.is_empty() {
             Err(format_err!(
@@ -71,25 +289,222 @@ impl BackendDefinition {
     }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct ProxyConfig {
+    #[serde(default)]
     pub(crate) backends: Vec<BackendDefinition>,
+    /// Additional backend definitions read from GeoJSON features
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) geojson: Option<GeoJsonSource>,
     pub(crate) default_backend: Backend,
+    /// Backend selection for points outside of every area
+    #[serde(default)]
+    pub(crate) fallback: Fallback<f64>,
+    #[serde(default)]
+    pub(crate) overlap_strategy: OverlapStrategy,
+    /// Sources of the request location, in order of precedence
+    #[serde(default = "default_sources")]
+    pub(crate) location: Vec<LocationSource>,
+    /// Headers added to, or removed from, proxied requests and responses
+    #[serde(default)]
+    pub(crate) headers: HeadersConfig,
+    /// Number of times failed idempotent requests are retried with the next backend
+    #[serde(default)]
+    pub(crate) retries: usize,
+    /// Serve HTTPS instead of plain HTTP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) tls: Option<TlsConfig>,
+    /// Close unclosed area rings and fix their winding, instead of rejecting them
+    #[serde(default)]
+    pub(crate) repair_areas: bool,
+    /// Whether coordinates are planar, or longitudes and latitudes
+    #[serde(default)]
+    pub(crate) coordinate_system: CoordinateSystem,
+    /// Order of the coordinates of the areas declared in the config
+    #[serde(default)]
+    pub(crate) axis_order: AxisOrder,
+    /// Precision of the coordinates in the area index
+    #[serde(default)]
+    pub(crate) coordinate_precision: Precision,
 }
 
 impl ProxyConfig {
+    /// Swap the areas between the declared axis order and the longitude first one of the index,
+    /// swapping them again restores them
+    fn swap_axes(&mut self) {
+        let axis_order = self.axis_order;
+
+        self.backends
+            .iter_mut()
+            .flat_map(|definition| definition.areas.iter_mut())
+            .for_each(|area| axis_order.normalize_polygon(area));
+    }
+
+    /// Config as JSON, with the areas in the declared axis order so that it reads back the same
+    pub(crate) fn dump(&mut self) -> serde_json::Value {
+        self.swap_axes();
+        let json = serde_json::to_value(&*self).unwrap_or_default();
+        self.swap_axes();
+
+        json
+    }
+
     fn validate(&self) -> Result<()> {
         self.backends
             .iter()
-            .map(|backend| backend.validate())
-            .collect::<Result<()>>()
+            .try_for_each(|backend| backend.validate())?;
+
+        let area_issues = self
+            .backends
+            .iter()
+            .flat_map(|backend| backend.area_issues(self.coordinate_system))
+            .collect::<Vec<_>>();
+
+        if !area_issues.is_empty() {
+            return Err(format_err!("Invalid areas: {}", area_issues.join("; ")));
+        }
+
+        if let Some(tls) = &self.tls {
+            tls.validate()?;
+        }
+
+        if self.location.is_empty() {
+            return Err(format_err!(
+                "At least one location source has to be declared"
+            ));
+        }
+
+        match self.fallback {
+            Fallback::Nearest {
+                max_distance: Some(max_distance),
+            } if max_distance.is_nan() || max_distance < 0.0 => Err(format_err!(
+                "Fallback max_distance has to be a non-negative number, {}",
+                max_distance
+            )),
+            _ => self.default_backend.validate(),
+        }
     }
 }
 
-pub(crate) fn read_config(source: impl AsRef<Path>) -> Result<ProxyConfig> {
-    let file = File::open(source)?;
-    let config: ProxyConfig = serde_json::from_reader(file)?;
+/// Read and validate the config,
+/// `precision` given on the command line takes precedence over the one in the config
+pub(crate) fn read_config(
+    source: impl AsRef<Path>,
+    precision: Option<Precision>,
+) -> Result<ProxyConfig> {
+    let file = File::open(&source)?;
+    let mut config: ProxyConfig = serde_json::from_reader(file)?;
+    let base_dir = source.as_ref().parent().unwrap_or_else(|| Path::new(""));
+
+    if let Some(precision) = precision {
+        config.coordinate_precision = precision;
+    }
+
+    // GeoJSON features are always longitude first
+    config.swap_axes();
+
+    if let Some(geojson) = config.geojson.take() {
+        config.backends.extend(geojson.into_definitions(base_dir)?);
+    }
+
+    if let Some(tls) = &mut config.tls {
+        tls.resolve_paths(base_dir);
+    }
+
+    if config.repair_areas {
+        config
+            .backends
+            .iter_mut()
+            .for_each(BackendDefinition::repair_areas);
+    }
+
     config.validate()?;
 
+    config
+        .backends
+        .iter_mut()
+        .map(|definition| &mut definition.backend)
+        .chain(Some(&mut config.default_backend))
+        .try_for_each(|backend| backend.setup_client(base_dir))?;
+
     Ok(config)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo_types::Coordinate;
+    use serde_json::json;
+
+    use crate::testing::{temp_dir, write_config};
+
+    fn backend(methods: serde_json::Value) -> serde_json::Result<Backend> {
+        serde_json::from_value(json!({
+            "base_url": "http://upstream",
+            "methods": methods,
+        }))
+    }
+
+    #[test]
+    fn methods() {
+        let backend = backend(json!(["get", "Post"])).unwrap();
+
+        assert!(backend.allows_method(&Method::GET));
+        assert!(backend.allows_method(&Method::POST));
+        assert!(!backend.allows_method(&Method::PUT));
+        assert_eq!(backend.allow_header().unwrap(), "GET, POST");
+        assert!(backend.validate().is_ok());
+    }
+
+    #[test]
+    fn axis_order_dump() {
+        let dir = temp_dir();
+        let path = write_config(
+            &dir,
+            &json!({
+                "backends": [{
+                    "areas": [{
+                        "exterior": [{"x": 50, "y": 10}, {"x": 50, "y": 20}, {"x": 60, "y": 20}, {"x": 50, "y": 10}],
+                        "interiors": [],
+                    }],
+                    "backend": {"base_url": "http://europe"},
+                }],
+                "default_backend": {"base_url": "http://default"},
+                "axis_order": "lat_lon",
+            }),
+        );
+
+        let mut config = read_config(&path, None).unwrap();
+        let areas = config.backends[0].areas.clone();
+        assert_eq!(areas[0].exterior().0[1], Coordinate { x: 20., y: 50. });
+
+        // the dump is in the declared order, reading it back gives the same areas
+        let dump = config.dump();
+        assert_eq!(dump["axis_order"], "lat_lon");
+        assert_eq!(
+            dump["backends"][0]["areas"][0]["exterior"][1],
+            json!({"x": 50.0, "y": 20.0})
+        );
+
+        write_config(&dir, &dump);
+        assert_eq!(read_config(&path, None).unwrap().backends[0].areas, areas);
+    }
+
+    #[test]
+    fn no_upstreams() {
+        let backend: Backend = serde_json::from_value(json!({
+            "name": "europe",
+            "upstreams": [],
+        }))
+        .unwrap();
+
+        let error = backend.validate().unwrap_err().to_string();
+        assert!(error.contains("europe"), "{}", error);
+    }
+
+    #[test]
+    fn invalid_methods() {
+        assert!(backend(json!(["GET", "NOT A METHOD"])).is_err());
+        assert!(backend(json!([])).unwrap().validate().is_err());
+    }
+}
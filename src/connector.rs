@@ -0,0 +1,332 @@
+use crate::error::*;
+
+use futures::{future, Future, Poll};
+use hyper::client::connect::{
+    dns::TokioThreadpoolGaiResolver, Connect, Connected, Destination, HttpConnector,
+};
+use hyper::Client;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use openssl::x509::{store::X509StoreBuilder, X509};
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_openssl::ConnectConfigurationExt;
+
+use crate::tls::{handshake_error, read_file, Certificate, CertificateFiles, TlsStream};
+
+/// Client used to send requests to the upstreams of a backend
+pub(crate) type UpstreamClient = Client<HttpsConnector>;
+
+/// TLS settings used to connect to `https` upstreams of a backend
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct UpstreamTls {
+    /// PEM bundle of CA certificates trusted instead of the system ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) ca: Option<PathBuf>,
+    /// Client certificate presented to the upstreams (mutual TLS)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) client_cert: Option<CertificateFiles>,
+    /// Name sent with SNI and verified against the certificate, instead of the upstream host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) server_name: Option<String>,
+    /// Skip verification of upstream certificates, never use it in production
+    #[serde(default)]
+    pub(crate) insecure: bool,
+}
+
+impl UpstreamTls {
+    /// Resolve relative paths against the directory of the config file
+    pub(crate) fn resolve_paths(&mut self, base_dir: &Path) {
+        if let Some(ca) = &mut self.ca {
+            *ca = base_dir.join(&ca);
+        }
+
+        if let Some(files) = &mut self.client_cert {
+            files.cert = base_dir.join(&files.cert);
+            files.key = base_dir.join(&files.key);
+        }
+    }
+
+    fn connector(&self) -> Result<SslConnector> {
+        let mut builder = SslConnector::builder(SslMethod::tls())?;
+
+        if let Some(ca) = &self.ca {
+            let mut store = X509StoreBuilder::new()?;
+
+            X509::stack_from_pem(&read_file(ca)?)?
+                .into_iter()
+                .try_for_each(|cert| store.add_cert(cert))?;
+
+            builder.set_cert_store(store.build());
+        }
+
+        if let Some(files) = &self.client_cert {
+            let certificate = Certificate::load(files)?;
+
+            builder.set_certificate(&certificate.chain[0])?;
+            certificate.chain[1..]
+                .iter()
+                .try_for_each(|cert| builder.add_extra_chain_cert(cert.clone()))?;
+            builder.set_private_key(&certificate.key)?;
+            builder.check_private_key()?;
+        }
+
+        if self.insecure {
+            builder.set_verify(SslVerifyMode::NONE);
+        }
+
+        Ok(builder.build())
+    }
+}
+
+/// Connector handling both `http` and `https` upstreams
+#[derive(Clone)]
+pub(crate) struct HttpsConnector {
+    http: HttpConnector<TokioThreadpoolGaiResolver>,
+    tls: SslConnector,
+    server_name: Option<String>,
+    insecure: bool,
+}
+
+impl HttpsConnector {
+    pub(crate) fn new(tls: &UpstreamTls, connect_timeout: Option<Duration>) -> Result<Self> {
+        let mut http = HttpConnector::new_with_tokio_threadpool_resolver();
+        http.enforce_http(false);
+        http.set_connect_timeout(connect_timeout);
+
+        Ok(Self {
+            http,
+            tls: tls.connector()?,
+            server_name: tls.server_name.clone(),
+            insecure: tls.insecure,
+        })
+    }
+
+    pub(crate) fn client(
+        tls: &UpstreamTls,
+        connect_timeout: Option<Duration>,
+    ) -> Result<UpstreamClient> {
+        Ok(Client::builder().build(Self::new(tls, connect_timeout)?))
+    }
+}
+
+impl fmt::Debug for HttpsConnector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HttpsConnector")
+            .field("server_name", &self.server_name)
+            .field("insecure", &self.insecure)
+            .finish()
+    }
+}
+
+impl Connect for HttpsConnector {
+    type Transport = MaybeTls<TcpStream>;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = (Self::Transport, Connected), Error = io::Error> + Send>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let https = dst.scheme() == "https";
+        let server_name = self
+            .server_name
+            .clone()
+            .unwrap_or_else(|| dst.host().to_owned());
+
+        let connecting = self.http.connect(dst);
+
+        if !https {
+            return Box::new(
+                connecting.map(|(stream, connected)| (MaybeTls::Plain(stream), connected)),
+            );
+        }
+
+        let config = self.tls.configure().map(|mut config| {
+            if self.insecure {
+                config.set_verify_hostname(false);
+            }
+
+            config
+        });
+
+        Box::new(connecting.and_then(move |(stream, connected)| {
+            future::result(config.map_err(io::Error::other))
+                .and_then(move |config| {
+                    config
+                        .connect_async(&server_name, stream)
+                        .map_err(handshake_error)
+                })
+                .map(|stream| (MaybeTls::Tls(stream), connected))
+        }))
+    }
+}
+
+/// Upstream connection, encrypted for `https` upstreams
+#[derive(Debug)]
+pub(crate) enum MaybeTls<S> {
+    Plain(S),
+    Tls(TlsStream<S>),
+}
+
+impl<S: Read + Write> Read for MaybeTls<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            MaybeTls::Plain(stream) => stream.read(buf),
+            MaybeTls::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl<S: Read + Write> Write for MaybeTls<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            MaybeTls::Plain(stream) => stream.write(buf),
+            MaybeTls::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            MaybeTls::Plain(stream) => stream.flush(),
+            MaybeTls::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncRead for MaybeTls<S> {}
+
+impl<S: AsyncRead + AsyncWrite> AsyncWrite for MaybeTls<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match self {
+            MaybeTls::Plain(stream) => stream.shutdown(),
+            MaybeTls::Tls(stream) => stream.shutdown(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::ssl::{SslAcceptor, SslFiletype};
+    use serde_json::json;
+    use std::net::TcpListener;
+    use std::thread;
+    use tokio::runtime::Runtime;
+
+    use crate::config::read_config;
+    use crate::testing::{temp_dir, write_certificate, write_config};
+
+    /// HTTPS server answering every request with an empty 200 response
+    fn serve(files: &CertificateFiles) -> u16 {
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+        acceptor.set_certificate_chain_file(&files.cert).unwrap();
+        acceptor
+            .set_private_key_file(&files.key, SslFiletype::PEM)
+            .unwrap();
+        let acceptor = acceptor.build();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if let Ok(mut stream) = acceptor.accept(stream.unwrap()) {
+                    let mut buffer = [0; 1024];
+                    if stream.read(&mut buffer).unwrap_or(0) > 0 {
+                        stream
+                            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                            .ok();
+                    }
+                }
+            }
+        });
+
+        port
+    }
+
+    fn request(tls: serde_json::Value, port: u16) -> bool {
+        let tls: UpstreamTls = serde_json::from_value(tls).unwrap();
+        let client = HttpsConnector::client(&tls, None).unwrap();
+        let uri = format!("https://127.0.0.1:{}/", port).parse().unwrap();
+
+        Runtime::new()
+            .unwrap()
+            .block_on(client.get(uri))
+            .map(|resp| resp.status().is_success())
+            .unwrap_or(false)
+    }
+
+    #[test]
+    fn upstream_verification() {
+        let dir = temp_dir();
+        let (files, _) = write_certificate(&dir, "upstream.test");
+        let port = serve(&files);
+        let ca = &files.cert;
+
+        // the certificate is only trusted with the custom CA, and valid for its name only
+        assert!(!request(json!({}), port));
+        assert!(!request(json!({ "ca": ca }), port));
+        assert!(request(
+            json!({ "ca": ca, "server_name": "upstream.test" }),
+            port
+        ));
+        assert!(!request(
+            json!({ "ca": ca, "server_name": "other.test" }),
+            port
+        ));
+        assert!(request(json!({ "insecure": true }), port));
+    }
+
+    #[test]
+    fn relative_paths() {
+        let mut tls: UpstreamTls = serde_json::from_value(json!({
+            "ca": "certs/ca.pem",
+            "client_cert": {"cert": "/etc/geoproxy/client.pem", "key": "client.key"},
+        }))
+        .unwrap();
+
+        tls.resolve_paths(Path::new("/etc/geoproxy"));
+
+        assert_eq!(tls.ca, Some(PathBuf::from("/etc/geoproxy/certs/ca.pem")));
+        assert_eq!(
+            tls.client_cert,
+            Some(CertificateFiles {
+                cert: PathBuf::from("/etc/geoproxy/client.pem"),
+                key: PathBuf::from("/etc/geoproxy/client.key"),
+            })
+        );
+    }
+
+    #[test]
+    fn missing_ca() {
+        let dir = temp_dir();
+        write_certificate(&dir, "upstream.test");
+        let config = |ca: &str| {
+            json!({
+                "backends": [{
+                    "areas": [{
+                        "exterior": [{"x": 0, "y": 0}, {"x": 1, "y": 0}, {"x": 0, "y": 1}, {"x": 0, "y": 0}],
+                        "interiors": [],
+                    }],
+                    "backend": {
+                        "name": "secure",
+                        "base_url": "https://upstream.test",
+                        "tls": {"ca": ca},
+                    },
+                }],
+                "default_backend": {"base_url": "http://default"},
+            })
+        };
+
+        // relative to the config file
+        let path = write_config(&dir, &config("upstream.test.pem"));
+        assert!(read_config(&path, None).is_ok());
+
+        write_config(&dir, &config("missing.pem"));
+        let error = read_config(&path, None).unwrap_err().to_string();
+        assert!(error.contains("backend secure"), "{}", error);
+        assert!(error.contains("missing.pem"), "{}", error);
+    }
+}
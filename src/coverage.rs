@@ -0,0 +1,114 @@
+use crate::error::*;
+
+use failure::format_err;
+use geo_types::{MultiPolygon, Polygon};
+use geoindex::{CoordinateSystem, Coverage};
+use geojson::{Feature, GeoJson, Geometry, Value};
+use std::fs::File;
+
+use crate::area::area_issues;
+use crate::config::read_config;
+use crate::features::{convert_polygon, polygon_positions};
+use crate::index::Precision;
+use crate::util::setup_index;
+
+/// Polygons of every geometry in the GeoJSON file
+fn read_region(path: &str, system: CoordinateSystem) -> Result<Vec<Polygon<f64>>> {
+    let file =
+        File::open(path).map_err(|error| format_err!("Unable to open {}: {}", path, error))?;
+
+    let geometries = match serde_json::from_reader(file)? {
+        GeoJson::Geometry(geometry) => vec![Some(geometry)],
+        GeoJson::Feature(feature) => vec![feature.geometry],
+        GeoJson::FeatureCollection(collection) => collection
+            .features
+            .into_iter()
+            .map(|feature| feature.geometry)
+            .collect(),
+    };
+
+    let mut polygons = Vec::new();
+
+    for geometry in geometries.into_iter().flatten() {
+        match geometry.value {
+            Value::Polygon(polygon) => polygons.push(convert_polygon(polygon)?),
+            Value::MultiPolygon(multi) => {
+                for polygon in multi {
+                    polygons.push(convert_polygon(polygon)?);
+                }
+            }
+            other => {
+                return Err(format_err!(
+                    "Region has an unsupported geometry type {}",
+                    other.type_name()
+                ));
+            }
+        }
+    }
+
+    let issues = polygons
+        .iter()
+        .enumerate()
+        .flat_map(|(index, polygon)| {
+            area_issues(polygon, system)
+                .into_iter()
+                .map(move |issue| format!("polygon #{}: {}", index, issue))
+        })
+        .collect::<Vec<_>>();
+
+    if polygons.is_empty() {
+        Err(format_err!("Region {} has no polygons", path))
+    } else if !issues.is_empty() {
+        Err(format_err!("Invalid region: {}", issues.join("; ")))
+    } else {
+        Ok(polygons)
+    }
+}
+
+/// Print the parts of the region not covered by any backend area as a GeoJSON feature,
+/// along with the uncovered share of the region
+pub(crate) fn coverage_report(
+    config_path: &str,
+    precision: Option<Precision>,
+    region_path: &str,
+) -> Result<()> {
+    let config = read_config(config_path, precision)?;
+    let system = config.coordinate_system;
+    let index = setup_index(config);
+
+    let coverage = read_region(region_path, system)?
+        .iter()
+        .map(|part| index.coverage(part))
+        .fold(
+            Coverage {
+                uncovered: MultiPolygon(Vec::new()),
+                uncovered_area: 0.,
+                region_area: 0.,
+            },
+            |mut total, part| {
+                total.uncovered.0.extend(part.uncovered.0);
+                total.uncovered_area += part.uncovered_area;
+                total.region_area += part.region_area;
+                total
+            },
+        );
+
+    let mut feature = Feature::from(Geometry::new(Value::MultiPolygon(
+        coverage.uncovered.0.iter().map(polygon_positions).collect(),
+    )));
+    feature.set_property("uncovered_area", coverage.uncovered_area);
+    feature.set_property("region_area", coverage.region_area);
+    feature.set_property("uncovered_percentage", coverage.uncovered_percentage());
+
+    println!("{}", GeoJson::from(feature));
+
+    eprintln!(
+        "{}: {}% uncovered, area {} of {}",
+        region_path,
+        coverage.uncovered_percentage(),
+        coverage.uncovered_area,
+        coverage.region_area
+    );
+
+    Ok(())
+}
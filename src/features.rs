@@ -0,0 +1,207 @@
+use crate::error::*;
+use failure::format_err;
+use geo_types::{Coordinate, LineString, Polygon};
+use geojson::{FeatureCollection, PolygonType, Position, Value};
+use serde_derive::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::config::{Backend, BackendDefinition};
+
+/// GeoJSON feature collection, declared inline or as a path to a file
+///
+/// Relative paths are resolved against the directory of the config file.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum GeoJsonSource {
+    File(PathBuf),
+    Inline(Box<FeatureCollection>),
+}
+
+/// Backend fields read from the feature properties
+#[derive(Debug, Deserialize)]
+struct FeatureProperties {
+    #[serde(flatten)]
+    backend: Backend,
+    #[serde(default)]
+    priority: Option<i32>,
+}
+
+impl GeoJsonSource {
+    fn load(self, base_dir: &Path) -> Result<FeatureCollection> {
+        match self {
+            GeoJsonSource::File(path) => {
+                let path = base_dir.join(path);
+                let file = File::open(&path)
+                    .map_err(|error| format_err!("Unable to open {}: {}", path.display(), error))?;
+
+                Ok(serde_json::from_reader(file)?)
+            }
+            GeoJsonSource::Inline(collection) => Ok(*collection),
+        }
+    }
+
+    /// Convert every feature of the collection into a backend definition
+    pub(crate) fn into_definitions(self, base_dir: &Path) -> Result<Vec<BackendDefinition>> {
+        self.load(base_dir)?
+            .features
+            .into_iter()
+            .enumerate()
+            .map(|(id, feature)| {
+                let areas = match feature.geometry.map(|geometry| geometry.value) {
+                    Some(Value::Polygon(polygon)) => vec![convert_polygon(polygon)?],
+                    Some(Value::MultiPolygon(polygons)) => polygons
+                        .into_iter()
+                        .map(convert_polygon)
+                        .collect::<Result<_>>()?,
+                    Some(other) => {
+                        return Err(format_err!(
+                            "GeoJSON feature #{} has an unsupported geometry type {}",
+                            id,
+                            other.type_name()
+                        ));
+                    }
+                    None => {
+                        return Err(format_err!("GeoJSON feature #{} has no geometry", id));
+                    }
+                };
+
+                let properties = feature.properties.unwrap_or_default();
+                let FeatureProperties { backend, priority } =
+                    serde_json::from_value(properties.into()).map_err(|error| {
+                        format_err!("GeoJSON feature #{} has invalid properties: {}", id, error)
+                    })?;
+
+                Ok(BackendDefinition {
+                    areas,
+                    backend,
+                    priority,
+                })
+            })
+            .collect()
+    }
+}
+
+fn convert_ring(ring: Vec<Position>) -> Result<LineString<f64>> {
+    ring.into_iter()
+        .map(|position| match position.as_slice() {
+            [x, y, ..] => Ok(Coordinate { x: *x, y: *y }),
+            _ => Err(format_err!(
+                "GeoJSON position needs at least two coordinates, got {:?}",
+                position
+            )),
+        })
+        .collect::<Result<Vec<_>>>()
+        .map(LineString::from)
+}
+
+pub(crate) fn convert_polygon(polygon: PolygonType) -> Result<Polygon<f64>> {
+    let mut rings = polygon.into_iter().map(convert_ring);
+
+    let exterior = rings
+        .next()
+        .ok_or_else(|| format_err!("GeoJSON polygon has no exterior ring"))??;
+    let interiors = rings.collect::<Result<_>>()?;
+
+    Ok(Polygon::new(exterior, interiors))
+}
+
+/// GeoJSON rings of the polygon, the exterior first
+pub(crate) fn polygon_positions(polygon: &Polygon<f64>) -> PolygonType {
+    Some(polygon.exterior())
+        .into_iter()
+        .chain(polygon.interiors())
+        .map(|ring| {
+            ring.0
+                .iter()
+                .map(|coordinate| vec![coordinate.x, coordinate.y])
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collection() -> GeoJsonSource {
+        serde_json::from_str(
+            r#"{
+                "type": "FeatureCollection",
+                "features": [
+                    {
+                        "type": "Feature",
+                        "geometry": {
+                            "type": "Polygon",
+                            "coordinates": [[[0, 0], [0, 5], [5, 5], [5, 0], [0, 0]]]
+                        },
+                        "properties": {
+                            "base_url": "http://backend1",
+                            "priority": 3,
+                            "region": "ignored"
+                        }
+                    },
+                    {
+                        "type": "Feature",
+                        "geometry": {
+                            "type": "MultiPolygon",
+                            "coordinates": [
+                                [[[0, 0], [0, -5], [-5, -5], [-5, 0], [0, 0]]],
+                                [
+                                    [[10, 10], [10, 20], [20, 20], [20, 10], [10, 10]],
+                                    [[12, 12], [12, 14], [14, 14], [14, 12], [12, 12]]
+                                ]
+                            ]
+                        },
+                        "properties": {
+                            "base_url": "http://backend2",
+                            "methods": ["GET"]
+                        }
+                    }
+                ]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn inline_collection() {
+        let defs = collection().into_definitions(Path::new(".")).unwrap();
+
+        assert_eq!(defs.len(), 2);
+
+        assert_eq!(defs[0].areas.len(), 1);
+        assert_eq!(defs[0].priority, Some(3));
+        assert_eq!(format!("{}", defs[0].backend), "http://backend1/");
+
+        assert_eq!(defs[1].areas.len(), 2);
+        assert_eq!(defs[1].areas[1].interiors().len(), 1);
+        assert_eq!(defs[1].priority, None);
+    }
+
+    #[test]
+    fn file_reference() {
+        let source: GeoJsonSource = serde_json::from_str(r#""areas.geojson""#).unwrap();
+
+        assert_eq!(source, GeoJsonSource::File(PathBuf::from("areas.geojson")));
+    }
+
+    #[test]
+    fn unsupported_geometry() {
+        let source: GeoJsonSource = serde_json::from_str(
+            r#"{
+                "type": "FeatureCollection",
+                "features": [
+                    {
+                        "type": "Feature",
+                        "geometry": {"type": "Point", "coordinates": [1, 2]},
+                        "properties": {"base_url": "http://backend1"}
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert!(source.into_definitions(Path::new(".")).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use crate::error::*;
+use log::*;
+
+use geo_types::Point;
+use http::Request;
+use ipnetwork::IpNetwork;
+use maxminddb::{geoip2, Reader};
+use std::net::IpAddr;
+use std::path::Path;
+
+static FORWARDED_FOR: &str = "X-Forwarded-For";
+
+pub(crate) fn is_trusted(trusted_proxies: &[IpNetwork], addr: IpAddr) -> bool {
+    trusted_proxies.iter().any(|network| network.contains(addr))
+}
+
+/// Address of the client, `X-Forwarded-For` is only taken into account
+/// when the request comes through trusted proxies
+pub(crate) fn client_addr<B>(
+    trusted_proxies: &[IpNetwork],
+    peer: IpAddr,
+    req: &Request<B>,
+) -> IpAddr {
+    if !is_trusted(trusted_proxies, peer) {
+        return peer;
+    }
+
+    let forwarded = req
+        .headers()
+        .get_all(FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|addr| addr.trim().parse::<IpAddr>().ok())
+        .collect::<Option<Vec<_>>>()
+        .unwrap_or_default();
+
+    // walk back the chain of proxies, up to the first untrusted address
+    let mut client = peer;
+
+    for addr in forwarded.into_iter().rev() {
+        client = addr;
+
+        if !is_trusted(trusted_proxies, addr) {
+            break;
+        }
+    }
+
+    client
+}
+
+/// Client location lookup in a local MaxMind (.mmdb) database
+pub(crate) struct GeoIp {
+    reader: Reader<Vec<u8>>,
+    trusted_proxies: Vec<IpNetwork>,
+}
+
+impl GeoIp {
+    pub(crate) fn open(path: impl AsRef<Path>, trusted_proxies: Vec<IpNetwork>) -> Result<Self> {
+        let reader = Reader::open_readfile(&path)?;
+
+        info!(
+            "Using GeoIP database {} ({})",
+            path.as_ref().display(),
+            reader.metadata.database_type
+        );
+
+        Ok(Self {
+            reader,
+            trusted_proxies,
+        })
+    }
+
+    pub(crate) fn lookup(&self, addr: IpAddr) -> Option<Point<f64>> {
+        let city = self
+            .reader
+            .lookup::<geoip2::City>(addr)
+            .map_err(|error| debug!("GeoIP lookup of {} failed: {}", addr, error))
+            .ok()?;
+
+        let location = city.location?;
+
+        Some(Point::new(location.longitude?, location.latitude?))
+    }
+
+    /// Location of the client of the request
+    pub(crate) fn locate<B>(&self, peer: IpAddr, req: &Request<B>) -> Option<Point<f64>> {
+        self.lookup(client_addr(&self.trusted_proxies, peer, req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{temp_dir, write_geoip_db};
+
+    fn request(forwarded: &str) -> Request<()> {
+        Request::builder()
+            .header(FORWARDED_FOR, forwarded)
+            .body(())
+            .unwrap()
+    }
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer() {
+        let trusted = vec!["10.0.0.0/8".parse().unwrap()];
+        let req = request("1.2.3.4");
+
+        assert_eq!(client_addr(&trusted, ip("8.8.8.8"), &req), ip("8.8.8.8"));
+    }
+
+    #[test]
+    fn trusted_chain() {
+        let trusted = vec!["10.0.0.0/8".parse().unwrap()];
+        let req = request("6.6.6.6, 1.2.3.4, 10.0.0.2");
+
+        assert_eq!(client_addr(&trusted, ip("10.0.0.1"), &req), ip("1.2.3.4"));
+    }
+
+    #[test]
+    fn invalid_header() {
+        let trusted = vec!["10.0.0.0/8".parse().unwrap()];
+        let req = request("1.2.3.4, garbage");
+
+        assert_eq!(client_addr(&trusted, ip("10.0.0.1"), &req), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn lookup() {
+        let dir = temp_dir();
+        let path = write_geoip_db(&dir, &[("81.2.69.142/31", (-0.0931, 51.5142))]);
+        let trusted = vec!["10.0.0.0/8".parse().unwrap()];
+        let geoip = GeoIp::open(path, trusted).unwrap();
+
+        assert_eq!(
+            geoip.lookup(ip("81.2.69.143")),
+            Some(Point::new(-0.0931, 51.5142))
+        );
+        assert_eq!(geoip.lookup(ip("81.2.69.144")), None);
+        assert_eq!(geoip.lookup(ip("127.0.0.1")), None);
+
+        // the client behind a trusted proxy is located
+        let req = request("81.2.69.142");
+        assert_eq!(
+            geoip.locate(ip("10.0.0.1"), &req),
+            Some(Point::new(-0.0931, 51.5142))
+        );
+        assert_eq!(geoip.locate(ip("8.8.8.8"), &req), None);
+    }
+}
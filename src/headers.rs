@@ -0,0 +1,337 @@
+use http::header::{
+    HeaderMap, HeaderName, HeaderValue, CONNECTION, FORWARDED, HOST, PROXY_AUTHENTICATE,
+    PROXY_AUTHORIZATION, TE, TRAILER, TRANSFER_ENCODING, UPGRADE, VIA,
+};
+use http::{Request, Response, Version};
+use serde_derive::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+static X_FORWARDED_FOR: &str = "x-forwarded-for";
+static X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+static X_FORWARDED_HOST: &str = "x-forwarded-host";
+static X_GEOPROXY_BACKEND: &str = "x-geoproxy-backend";
+static X_GEOPROXY_REGION: &str = "x-geoproxy-region";
+static KEEP_ALIVE: &str = "keep-alive";
+
+// pseudonym of the proxy in the Via header
+static VIA_NAME: &str = "geoproxy";
+
+/// Headers added to, or removed from, proxied requests and responses
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct HeadersConfig {
+    /// RFC 7239 `Forwarded` request header
+    pub(crate) forwarded: bool,
+    /// `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host` request headers
+    pub(crate) x_forwarded: bool,
+    /// `Via` request and response header
+    pub(crate) via: bool,
+    /// Remove hop-by-hop headers from requests and responses
+    pub(crate) strip_hop_by_hop: bool,
+    /// `X-Geoproxy-Backend` response header, with the upstream handling the request
+    pub(crate) backend_header: bool,
+    /// `X-Geoproxy-Region` response header, with the name of the backend handling the request
+    pub(crate) region_header: bool,
+}
+
+impl Default for HeadersConfig {
+    fn default() -> Self {
+        Self {
+            forwarded: true,
+            x_forwarded: true,
+            via: true,
+            strip_hop_by_hop: true,
+            backend_header: false,
+            region_header: false,
+        }
+    }
+}
+
+/// Connection the request came through
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Peer {
+    pub(crate) addr: IpAddr,
+    pub(crate) proto: &'static str,
+    /// Forwarding headers set by trusted proxies are kept as they are
+    pub(crate) trusted: bool,
+}
+
+/// Remove headers meant for a single connection only, along with the ones
+/// listed in the `Connection` header
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let listed = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect::<Vec<_>>();
+
+    for name in listed {
+        headers.remove(name);
+    }
+
+    for name in &[
+        CONNECTION,
+        PROXY_AUTHENTICATE,
+        PROXY_AUTHORIZATION,
+        TE,
+        TRAILER,
+        TRANSFER_ENCODING,
+        UPGRADE,
+    ] {
+        headers.remove(name);
+    }
+
+    headers.remove(KEEP_ALIVE);
+}
+
+/// Append a value to a comma separated list header, merging existing lines into one
+fn append(headers: &mut HeaderMap, name: &'static str, value: &str) {
+    let mut values = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>();
+    values.push(value);
+
+    if let Ok(value) = HeaderValue::from_str(&values.join(", ")) {
+        headers.insert(name, value);
+    }
+}
+
+fn version(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "0.9",
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_2 => "2",
+        _ => "1.1",
+    }
+}
+
+/// Node identifier of RFC 7239, IPv6 addresses have to be bracketed and quoted
+fn forwarded_node(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(addr) => addr.to_string(),
+        IpAddr::V6(addr) => format!("\"[{}]\"", addr),
+    }
+}
+
+/// Quote a value of a `Forwarded` pair, unless it is a plain token
+fn forwarded_value(value: &str) -> String {
+    let token = value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c));
+
+    if token {
+        value.to_owned()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+impl HeadersConfig {
+    pub(crate) fn rewrite_request<B>(&self, req: &mut Request<B>, peer: Peer) {
+        let host = req
+            .headers()
+            .get(HOST)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned)
+            .or_else(|| {
+                req.uri()
+                    .authority_part()
+                    .map(|authority| authority.to_string())
+            });
+        let version = version(req.version());
+        let headers = req.headers_mut();
+
+        if self.strip_hop_by_hop {
+            strip_hop_by_hop(headers);
+        }
+
+        if self.forwarded {
+            if !peer.trusted {
+                headers.remove(FORWARDED);
+            }
+
+            let mut element = format!("for={};proto={}", forwarded_node(peer.addr), peer.proto);
+
+            if let Some(host) = &host {
+                element.push_str(&format!(";host={}", forwarded_value(host)));
+            }
+
+            append(headers, FORWARDED.as_str(), &element);
+        }
+
+        if self.x_forwarded {
+            if !peer.trusted {
+                headers.remove(X_FORWARDED_FOR);
+                headers.remove(X_FORWARDED_PROTO);
+                headers.remove(X_FORWARDED_HOST);
+            }
+
+            append(headers, X_FORWARDED_FOR, &peer.addr.to_string());
+
+            if !headers.contains_key(X_FORWARDED_PROTO) {
+                headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static(peer.proto));
+            }
+
+            if let Some(host) = host.and_then(|host| HeaderValue::from_str(&host).ok()) {
+                headers.entry(X_FORWARDED_HOST).unwrap().or_insert(host);
+            }
+        }
+
+        if self.via {
+            append(headers, VIA.as_str(), &format!("{} {}", version, VIA_NAME));
+        }
+    }
+
+    pub(crate) fn rewrite_response<B>(
+        &self,
+        resp: &mut Response<B>,
+        backend: &str,
+        region: Option<&str>,
+    ) {
+        let version = version(resp.version());
+        let headers = resp.headers_mut();
+
+        if self.strip_hop_by_hop {
+            strip_hop_by_hop(headers);
+        }
+
+        if self.via {
+            append(headers, VIA.as_str(), &format!("{} {}", version, VIA_NAME));
+        }
+
+        if self.backend_header {
+            if let Ok(value) = HeaderValue::from_str(backend) {
+                headers.insert(X_GEOPROXY_BACKEND, value);
+            }
+        }
+
+        if self.region_header {
+            if let Some(value) = region.and_then(|region| HeaderValue::from_str(region).ok()) {
+                headers.insert(X_GEOPROXY_REGION, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)]) -> Request<()> {
+        let mut builder = Request::builder();
+        builder.uri("/path");
+
+        for (name, value) in headers {
+            builder.header(*name, *value);
+        }
+
+        builder.body(()).unwrap()
+    }
+
+    fn peer(addr: &str, trusted: bool) -> Peer {
+        Peer {
+            addr: addr.parse().unwrap(),
+            proto: "http",
+            trusted,
+        }
+    }
+
+    #[test]
+    fn hop_by_hop() {
+        let mut req = request(&[
+            ("Connection", "keep-alive, X-Custom"),
+            ("Keep-Alive", "timeout=5"),
+            ("X-Custom", "1"),
+            ("X-Other", "2"),
+        ]);
+
+        HeadersConfig::default().rewrite_request(&mut req, peer("1.2.3.4", false));
+
+        let headers = req.headers();
+        assert!(!headers.contains_key("Connection"));
+        assert!(!headers.contains_key("Keep-Alive"));
+        assert!(!headers.contains_key("X-Custom"));
+        assert_eq!(headers["X-Other"], "2");
+    }
+
+    #[test]
+    fn forwarded() {
+        let mut req = request(&[("Host", "example.com:8080")]);
+
+        HeadersConfig::default().rewrite_request(&mut req, peer("::1", false));
+
+        let headers = req.headers();
+        assert_eq!(
+            headers["Forwarded"],
+            "for=\"[::1]\";proto=http;host=\"example.com:8080\""
+        );
+        assert_eq!(headers["X-Forwarded-For"], "::1");
+        assert_eq!(headers["X-Forwarded-Proto"], "http");
+        assert_eq!(headers["X-Forwarded-Host"], "example.com:8080");
+        assert_eq!(headers["Via"], "1.1 geoproxy");
+    }
+
+    #[test]
+    fn trusted_chain() {
+        let forwarded = [
+            ("X-Forwarded-For", "5.6.7.8"),
+            ("X-Forwarded-Proto", "https"),
+            ("Forwarded", "for=5.6.7.8"),
+        ];
+
+        let mut req = request(&forwarded);
+        HeadersConfig::default().rewrite_request(&mut req, peer("10.0.0.1", true));
+
+        let headers = req.headers();
+        assert_eq!(headers["X-Forwarded-For"], "5.6.7.8, 10.0.0.1");
+        assert_eq!(headers["X-Forwarded-Proto"], "https");
+        assert_eq!(headers["Forwarded"], "for=5.6.7.8, for=10.0.0.1;proto=http");
+
+        // spoofed headers of untrusted clients are dropped
+        let mut req = request(&forwarded);
+        HeadersConfig::default().rewrite_request(&mut req, peer("10.0.0.1", false));
+
+        let headers = req.headers();
+        assert_eq!(headers["X-Forwarded-For"], "10.0.0.1");
+        assert_eq!(headers["X-Forwarded-Proto"], "http");
+        assert_eq!(headers["Forwarded"], "for=10.0.0.1;proto=http");
+    }
+
+    #[test]
+    fn disabled() {
+        let config = HeadersConfig {
+            forwarded: false,
+            x_forwarded: false,
+            via: false,
+            strip_hop_by_hop: false,
+            backend_header: false,
+            region_header: false,
+        };
+        let mut req = request(&[("Connection", "close")]);
+
+        config.rewrite_request(&mut req, peer("1.2.3.4", false));
+
+        assert_eq!(req.headers().len(), 1);
+    }
+
+    #[test]
+    fn response() {
+        let config = HeadersConfig {
+            backend_header: true,
+            region_header: true,
+            ..HeadersConfig::default()
+        };
+        let mut resp = Response::new(());
+
+        config.rewrite_response(&mut resp, "http://10.0.0.1/", Some("europe"));
+
+        let headers = resp.headers();
+        assert_eq!(headers["X-Geoproxy-Backend"], "http://10.0.0.1/");
+        assert_eq!(headers["X-Geoproxy-Region"], "europe");
+        assert_eq!(headers["Via"], "1.1 geoproxy");
+    }
+}
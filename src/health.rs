@@ -0,0 +1,290 @@
+use log::*;
+
+use futures::future::{self, Either};
+use hyper::rt::{self, Future, Stream};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::timer::{Interval, Timeout};
+
+use crate::balance::Upstream;
+use crate::config::Backend;
+use crate::connector::UpstreamClient;
+use crate::index::AreaIndex;
+use crate::metrics::*;
+
+fn default_path() -> String {
+    "/".to_owned()
+}
+
+fn default_interval_ms() -> u64 {
+    5000
+}
+
+fn default_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_healthy_threshold() -> usize {
+    2
+}
+
+fn default_unhealthy_threshold() -> usize {
+    3
+}
+
+/// Active health check settings of a backend
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct HealthCheck {
+    /// Path requested with GET, any 2xx response is considered a success
+    #[serde(default = "default_path")]
+    pub(crate) path: String,
+    #[serde(default = "default_interval_ms")]
+    pub(crate) interval_ms: u64,
+    #[serde(default = "default_timeout_ms")]
+    pub(crate) timeout_ms: u64,
+    /// Consecutive successes needed to mark an unhealthy backend as healthy
+    #[serde(default = "default_healthy_threshold")]
+    pub(crate) healthy_threshold: usize,
+    /// Consecutive failures needed to mark a healthy backend as unhealthy
+    #[serde(default = "default_unhealthy_threshold")]
+    pub(crate) unhealthy_threshold: usize,
+}
+
+impl HealthCheck {
+    fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    pub(crate) fn is_valid(&self) -> bool {
+        self.path.starts_with('/')
+            && self.interval_ms > 0
+            && self.timeout_ms > 0
+            && self.healthy_threshold > 0
+            && self.unhealthy_threshold > 0
+    }
+}
+
+/// Health of an upstream, upstreams are considered healthy until proven otherwise
+#[derive(Debug)]
+pub(crate) struct HealthState {
+    healthy: AtomicBool,
+    successes: AtomicUsize,
+    failures: AtomicUsize,
+}
+
+impl Default for HealthState {
+    fn default() -> Self {
+        Self {
+            healthy: AtomicBool::new(true),
+            successes: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl HealthState {
+    pub(crate) fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    fn copy_from(&self, other: &HealthState) {
+        self.healthy.store(other.is_healthy(), Ordering::Relaxed);
+        self.successes
+            .store(other.successes.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.failures
+            .store(other.failures.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+
+    /// Record the result of a check, returns the new health if it changed
+    fn record(&self, success: bool, check: &HealthCheck) -> Option<bool> {
+        let (streak, reset, threshold) = if success {
+            (&self.successes, &self.failures, check.healthy_threshold)
+        } else {
+            (&self.failures, &self.successes, check.unhealthy_threshold)
+        };
+
+        reset.store(0, Ordering::Relaxed);
+        let streak = streak.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if streak >= threshold && self.healthy.swap(success, Ordering::Relaxed) != success {
+            Some(success)
+        } else {
+            None
+        }
+    }
+}
+
+/// Upstreams of the backends with health checks
+fn checked_upstreams(index: &AreaIndex) -> impl Iterator<Item = &Upstream> {
+    index
+        .values()
+        .chain(Some(index.default()))
+        .filter(|backend| backend.health_check().is_some())
+        .flat_map(Backend::upstreams)
+}
+
+/// Carry the health of upstreams checked in both indexes over to the new one,
+/// so upstreams known to be down get no traffic after a reload
+pub(crate) fn carry_over(previous: &AreaIndex, index: &AreaIndex) {
+    let previous = checked_upstreams(previous)
+        .map(|upstream| (upstream.url(), upstream.health()))
+        .collect::<HashMap<_, _>>();
+
+    checked_upstreams(index).for_each(|upstream| {
+        if let Some(health) = previous.get(upstream.url()) {
+            upstream.health().copy_from(health);
+        }
+    });
+}
+
+/// Spawns health checks of backends onto the runtime
+///
+/// Checks stop by themselves once the index holding the backend is dropped,
+/// e.g. after a config reload.
+#[derive(Clone)]
+pub(crate) struct HealthChecker {
+    metrics: MetricsClient,
+}
+
+impl HealthChecker {
+    pub(crate) fn new(metrics: MetricsClient) -> Self {
+        Self { metrics }
+    }
+
+    pub(crate) fn spawn(&self, index: &AreaIndex) {
+        index
+            .values()
+            .chain(Some(index.default()))
+            .for_each(|backend| self.spawn_check(backend));
+    }
+
+    fn spawn_check(&self, backend: &Backend) {
+        if let Some(check) = backend.health_check() {
+            backend.upstreams().iter().for_each(|upstream| {
+                self.spawn_upstream_check(upstream, backend.client(), check.clone())
+            });
+        }
+    }
+
+    fn spawn_upstream_check(
+        &self,
+        upstream: &Upstream,
+        client: &UpstreamClient,
+        check: HealthCheck,
+    ) {
+        let url = upstream.health_url(&check.path);
+        let state = Arc::downgrade(upstream.health());
+        let name = format!("{}", upstream);
+        let metric = format!("health.{}", metric_segment(&name));
+
+        let client = client.clone();
+        let metrics = self.metrics.clone();
+
+        debug!("Starting health checks of {} ({})", name, url);
+
+        let task = Interval::new(Instant::now(), check.interval())
+            .map_err(|error| error!("Health check timer error: {}", error))
+            .for_each(move |_| {
+                // stop checking backends that are no longer in use
+                let state = match state.upgrade() {
+                    Some(state) => state,
+                    None => return Either::A(future::err(())),
+                };
+
+                let check = check.clone();
+                let name = name.clone();
+                let metric = metric.clone();
+                let metrics = metrics.clone();
+
+                Either::B(Timeout::new(client.get(url.clone()), check.timeout()).then(
+                    move |result| {
+                        let success = result
+                            .map(|resp| resp.status().is_success())
+                            .unwrap_or(false);
+
+                        if let Some(healthy) = state.record(success, &check) {
+                            if healthy {
+                                info!("Upstream {} is healthy", name);
+                                metrics.incr("health.up");
+                            } else {
+                                warn!("Upstream {} is unhealthy", name);
+                                metrics.incr("health.down");
+                            }
+
+                            metrics.gauge(&metric, healthy as u64);
+                        }
+
+                        Ok(())
+                    },
+                ))
+            });
+
+        rt::spawn(task);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check() -> HealthCheck {
+        serde_json::from_value(serde_json::json!({
+            "healthy_threshold": 2,
+            "unhealthy_threshold": 3,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn unhealthy_threshold() {
+        let (state, check) = (HealthState::default(), check());
+
+        assert_eq!(state.record(false, &check), None);
+        assert_eq!(state.record(false, &check), None);
+        assert!(state.is_healthy());
+        assert_eq!(state.record(false, &check), Some(false));
+        assert!(!state.is_healthy());
+        assert_eq!(state.record(false, &check), None);
+    }
+
+    #[test]
+    fn healthy_threshold() {
+        let (state, check) = (HealthState::default(), check());
+
+        (0..3).for_each(|_| {
+            state.record(false, &check);
+        });
+
+        assert_eq!(state.record(true, &check), None);
+        assert!(!state.is_healthy());
+        assert_eq!(state.record(true, &check), Some(true));
+        assert!(state.is_healthy());
+        assert_eq!(state.record(true, &check), None);
+    }
+
+    #[test]
+    fn streaks_reset() {
+        let (state, check) = (HealthState::default(), check());
+
+        // a success in between restarts the count of failures
+        state.record(false, &check);
+        state.record(false, &check);
+        state.record(true, &check);
+        assert_eq!(state.record(false, &check), None);
+        assert_eq!(state.record(false, &check), None);
+        assert_eq!(state.record(false, &check), Some(false));
+
+        // and a failure the count of successes
+        state.record(true, &check);
+        state.record(false, &check);
+        assert_eq!(state.record(true, &check), None);
+        assert_eq!(state.record(true, &check), Some(true));
+    }
+}
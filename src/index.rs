@@ -0,0 +1,259 @@
+use crate::error::*;
+
+use failure::format_err;
+use geo::algorithm::map_coords::MapCoords;
+use geo_types::{Coordinate, Point, Polygon, Rect};
+use geoindex::{
+    AreaDefinition, CoordinateSystem, Coverage, Fallback, GeoIndex, IndexCoordinate, LookupReason,
+    Overlap, OverlapStrategy,
+};
+use num_traits::NumCast;
+use serde_derive::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::config::Backend;
+
+/// Precision of the coordinates stored in the area index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Precision {
+    /// Single precision, areas are accurate to about a meter at high longitudes
+    #[default]
+    F32,
+    /// Double precision, for detailed areas such as city blocks
+    F64,
+}
+
+impl FromStr for Precision {
+    type Err = failure::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "f32" => Ok(Precision::F32),
+            "f64" => Ok(Precision::F64),
+            _ => Err(format_err!("Unknown coordinate precision {}", value)),
+        }
+    }
+}
+
+fn narrow<V: IndexCoordinate>(value: f64) -> V {
+    <V as NumCast>::from(value).expect("float coordinates convert between precisions")
+}
+
+fn widen<V: IndexCoordinate>(value: V) -> f64 {
+    value
+        .to_f64()
+        .expect("float coordinates convert between precisions")
+}
+
+fn widen_coordinate<V: IndexCoordinate>(coordinate: Coordinate<V>) -> Coordinate<f64> {
+    Coordinate {
+        x: widen(coordinate.x),
+        y: widen(coordinate.y),
+    }
+}
+
+fn widen_reason<V: IndexCoordinate>(reason: LookupReason<V>) -> LookupReason<f64> {
+    match reason {
+        LookupReason::NoLocation => LookupReason::NoLocation,
+        LookupReason::Area { skipped } => LookupReason::Area { skipped },
+        LookupReason::Nearest { skipped, distance } => LookupReason::Nearest {
+            skipped,
+            distance: widen(distance),
+        },
+        LookupReason::Outside { skipped } => LookupReason::Outside { skipped },
+    }
+}
+
+/// Index of the backend areas, with the configured coordinate precision
+///
+/// Coordinates are passed in and out as `f64`, whatever the precision of the index.
+#[derive(Debug)]
+pub(crate) enum AreaIndex {
+    F32(GeoIndex<Backend, f32>),
+    F64(GeoIndex<Backend, f64>),
+}
+
+macro_rules! with_index {
+    ($area_index:expr, $index:ident => $body:expr) => {
+        match $area_index {
+            AreaIndex::F32($index) => $body,
+            AreaIndex::F64($index) => $body,
+        }
+    };
+}
+
+/// Index of the given precision, with the areas and fallback converted to it
+fn build<V: IndexCoordinate>(
+    defs: Vec<AreaDefinition<Backend, f64>>,
+    default: Backend,
+    fallback: Fallback<f64>,
+    strategy: OverlapStrategy,
+    system: CoordinateSystem,
+) -> GeoIndex<Backend, V> {
+    let defs = defs.into_iter().map(|def| AreaDefinition {
+        polygons: def
+            .polygons
+            .iter()
+            .map(|polygon| polygon.map_coords(&|&(x, y)| (narrow(x), narrow(y))))
+            .collect(),
+        value: def.value,
+        priority: def.priority,
+    });
+    let fallback = match fallback {
+        Fallback::Default => Fallback::Default,
+        Fallback::Nearest { max_distance } => Fallback::Nearest {
+            max_distance: max_distance.map(narrow),
+        },
+    };
+
+    GeoIndex::new(defs, default)
+        .with_coordinate_system(system)
+        .with_strategy(strategy)
+        .with_fallback(fallback)
+}
+
+impl AreaIndex {
+    pub(crate) fn new(
+        precision: Precision,
+        defs: Vec<AreaDefinition<Backend, f64>>,
+        default: Backend,
+        fallback: Fallback<f64>,
+        strategy: OverlapStrategy,
+        system: CoordinateSystem,
+    ) -> Self {
+        match precision {
+            Precision::F32 => AreaIndex::F32(build(defs, default, fallback, strategy, system)),
+            Precision::F64 => AreaIndex::F64(build(defs, default, fallback, strategy, system)),
+        }
+    }
+
+    pub(crate) fn coordinate_system(&self) -> CoordinateSystem {
+        with_index!(self, index => index.coordinate_system())
+    }
+
+    /// All backends with areas in the index, without the default backend
+    pub(crate) fn values(&self) -> Box<dyn Iterator<Item = &Backend> + '_> {
+        with_index!(self, index => Box::new(index.values()))
+    }
+
+    pub(crate) fn default(&self) -> &Backend {
+        with_index!(self, index => index.default())
+    }
+
+    /// Backend accepted by the filter for the point, along with the reason it was selected
+    pub(crate) fn explain_by(
+        &self,
+        coords: Option<&Point<f64>>,
+        filter: impl Fn(&Backend) -> bool,
+    ) -> (&Backend, LookupReason<f64>) {
+        with_index!(self, index => {
+            let coords = coords.map(|coords| Point::new(narrow(coords.x()), narrow(coords.y())));
+            let (backend, reason) = index.explain_by(coords.as_ref(), filter);
+
+            (backend, widen_reason(reason))
+        })
+    }
+
+    /// Backends accepted by the filter, in the order lookups fall through them
+    pub(crate) fn lookup_chain_by(
+        &self,
+        coords: Option<&Point<f64>>,
+        filter: impl Fn(&Backend) -> bool,
+    ) -> Vec<&Backend> {
+        with_index!(self, index => {
+            let coords = coords.map(|coords| Point::new(narrow(coords.x()), narrow(coords.y())));
+
+            index.lookup_chain_by(coords.as_ref(), filter)
+        })
+    }
+
+    /// Intersections between areas of different backends, in declaration order
+    pub(crate) fn overlaps(&self) -> Vec<Overlap<'_, Backend, f64>> {
+        with_index!(self, index => index
+            .overlaps()
+            .into_iter()
+            .map(|overlap| Overlap {
+                first: overlap.first,
+                second: overlap.second,
+                area: widen(overlap.area),
+                bounds: Rect {
+                    min: widen_coordinate(overlap.bounds.min),
+                    max: widen_coordinate(overlap.bounds.max),
+                },
+            })
+            .collect())
+    }
+
+    /// Parts of the region not covered by any area
+    pub(crate) fn coverage(&self, region: &Polygon<f64>) -> Coverage<f64> {
+        with_index!(self, index => {
+            let coverage = index.coverage(&region.map_coords(&|&(x, y)| (narrow(x), narrow(y))));
+
+            Coverage {
+                uncovered: coverage.uncovered.map_coords(&|&(x, y)| (widen(x), widen(y))),
+                uncovered_area: widen(coverage.uncovered_area),
+                region_area: widen(coverage.region_area),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo_types::LineString;
+
+    fn index(precision: Precision) -> AreaIndex {
+        // a block narrower than the spacing of single precision floats at this longitude
+        let block = Polygon::new(
+            LineString::from(vec![
+                (170.000_001, 10.),
+                (170.000_004, 10.),
+                (170.000_004, 10.000_003),
+                (170.000_001, 10.000_003),
+            ]),
+            vec![],
+        );
+        let backend = |name: &str| -> Backend {
+            serde_json::from_value(serde_json::json!({
+                "name": name,
+                "base_url": "http://upstream",
+            }))
+            .unwrap()
+        };
+
+        AreaIndex::new(
+            precision,
+            vec![AreaDefinition {
+                polygons: vec![block],
+                value: backend("block"),
+                priority: None,
+            }],
+            backend("default"),
+            Fallback::Default,
+            OverlapStrategy::default(),
+            CoordinateSystem::Geographic,
+        )
+    }
+
+    #[test]
+    fn precision() {
+        let point = Point::new(170.000_002_5, 10.000_001_5);
+
+        let (single, double) = (index(Precision::F32), index(Precision::F64));
+
+        let (backend, reason) = double.explain_by(Some(&point), |_| true);
+        assert_eq!(backend.name(), Some("block"));
+        assert_eq!(reason, LookupReason::Area { skipped: 0 });
+
+        let (backend, _) = single.explain_by(Some(&point), |_| true);
+        assert_eq!(backend.name(), Some("default"));
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("f64".parse::<Precision>().unwrap(), Precision::F64);
+        assert!("f16".parse::<Precision>().is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use geo::algorithm::map_coords::MapCoordsInplace;
+use geo_types::{Point, Polygon};
+use geoindex::CoordinateSystem;
+use http::{header::COOKIE, Request};
+use serde_derive::{Deserialize, Serialize};
+use url::{form_urlencoded, percent_encoding::percent_decode};
+
+fn default_geo_position_header() -> String {
+    "Geo-Position".to_owned()
+}
+
+/// Order of the coordinates in `[x, y]` pairs of the config areas, the JSON location header
+/// and the admin lookup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AxisOrder {
+    /// Longitude first, as in GeoJSON
+    #[default]
+    LonLat,
+    /// Latitude first
+    LatLon,
+}
+
+impl AxisOrder {
+    /// Point with the longitude as the `x` coordinate, as used by the index
+    pub(crate) fn normalize(self, point: Point<f64>) -> Point<f64> {
+        match self {
+            AxisOrder::LonLat => point,
+            AxisOrder::LatLon => Point::new(point.y(), point.x()),
+        }
+    }
+
+    /// Polygon with longitudes as the `x` coordinates, as used by the index
+    pub(crate) fn normalize_polygon(self, polygon: &mut Polygon<f64>) {
+        if self == AxisOrder::LatLon {
+            polygon.map_coords_inplace(&|&(x, y)| (y, x));
+        }
+    }
+}
+
+/// Source of the request location
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum LocationSource {
+    /// JSON `[x, y]` array in a header, e.g. `Geolocation: [x, y]`
+    JsonHeader { name: String },
+    /// Latitude and longitude in separate headers
+    Headers { lat: String, lon: String },
+    /// `lat,lon` query parameter
+    Query { name: String },
+    /// `lat,lon` cookie
+    Cookie { name: String },
+    /// `lat;lon` header, as used by the Geo-Position draft
+    GeoPosition {
+        #[serde(default = "default_geo_position_header")]
+        name: String,
+    },
+}
+
+pub(crate) fn default_sources() -> Vec<LocationSource> {
+    vec![LocationSource::JsonHeader {
+        name: "Geolocation".to_owned(),
+    }]
+}
+
+/// Latitude/longitude pair as a point, longitude being the `x` coordinate
+fn lat_lon(lat: &str, lon: &str) -> Option<Point<f64>> {
+    let lat = lat.trim().parse::<f64>().ok()?;
+    let lon = lon.trim().parse::<f64>().ok()?;
+
+    if lat.is_finite() && lon.is_finite() {
+        Some(Point::new(lon, lat))
+    } else {
+        None
+    }
+}
+
+fn split_lat_lon(value: &str, separator: char) -> Option<Point<f64>> {
+    let mut parts = value.splitn(2, separator);
+
+    lat_lon(parts.next()?, parts.next()?)
+}
+
+impl LocationSource {
+    fn header<'a, B>(req: &'a Request<B>, name: &str) -> Option<&'a str> {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    fn cookie<B>(req: &Request<B>, name: &str) -> Option<String> {
+        req.headers()
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|pair| {
+                let mut pair = pair.trim().splitn(2, '=');
+
+                match (pair.next(), pair.next()) {
+                    (Some(key), Some(value)) if key == name => Some(value),
+                    _ => None,
+                }
+            })
+            .next()
+            .and_then(|value| percent_decode(value.as_bytes()).decode_utf8().ok())
+            .map(|value| value.into_owned())
+    }
+
+    pub(crate) fn extract<B>(&self, req: &Request<B>, axes: AxisOrder) -> Option<Point<f64>> {
+        match self {
+            LocationSource::JsonHeader { name } => Self::header(req, name)
+                .and_then(|value| serde_json::from_str::<Point<f64>>(value).ok())
+                .filter(|point| point.x().is_finite() && point.y().is_finite())
+                .map(|point| axes.normalize(point)),
+            LocationSource::Headers { lat, lon } => {
+                lat_lon(Self::header(req, lat)?, Self::header(req, lon)?)
+            }
+            LocationSource::Query { name } => req.uri().query().and_then(|query| {
+                form_urlencoded::parse(query.as_bytes())
+                    .find(|(key, _)| key == name)
+                    .and_then(|(_, value)| split_lat_lon(&value, ','))
+            }),
+            LocationSource::Cookie { name } => {
+                Self::cookie(req, name).and_then(|value| split_lat_lon(&value, ','))
+            }
+            LocationSource::GeoPosition { name } => Self::header(req, name)
+                // optional parameters (e.g. `epu=50`) follow the coordinates
+                .and_then(|value| value.split_whitespace().next())
+                .and_then(|value| {
+                    let mut parts = value.split(';');
+
+                    lat_lon(parts.next()?, parts.next()?)
+                }),
+        }
+    }
+}
+
+/// Location of the request, taken from the first source able to provide it,
+/// locations outside of the coordinate ranges are skipped
+pub(crate) fn locate<B>(
+    sources: &[LocationSource],
+    axes: AxisOrder,
+    system: CoordinateSystem,
+    req: &Request<B>,
+) -> Option<Point<f64>> {
+    sources
+        .iter()
+        .filter_map(|source| source.extract(req, axes))
+        .find(|point| system.is_valid(point.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(uri: &str, headers: &[(&str, &str)]) -> Request<()> {
+        let mut builder = Request::builder();
+        builder.uri(uri);
+
+        for (name, value) in headers {
+            builder.header(*name, *value);
+        }
+
+        builder.body(()).unwrap()
+    }
+
+    fn locate_planar<B>(sources: &[LocationSource], req: &Request<B>) -> Option<Point<f64>> {
+        locate(sources, AxisOrder::LonLat, CoordinateSystem::Planar, req)
+    }
+
+    #[test]
+    fn json_header() {
+        let req = request("/", &[("Geolocation", "[2.0, 3.5]")]);
+
+        assert_eq!(
+            locate_planar(&default_sources(), &req),
+            Some(Point::new(2.0, 3.5))
+        );
+        assert_eq!(locate_planar(&default_sources(), &request("/", &[])), None);
+    }
+
+    #[test]
+    fn axis_order() {
+        let req = request("/", &[("Geolocation", "[52.2, 21.0]")]);
+
+        assert_eq!(
+            locate(
+                &default_sources(),
+                AxisOrder::LatLon,
+                CoordinateSystem::Planar,
+                &req
+            ),
+            Some(Point::new(21.0, 52.2))
+        );
+    }
+
+    #[test]
+    fn out_of_range() {
+        let sources = vec![
+            LocationSource::JsonHeader {
+                name: "Geolocation".to_owned(),
+            },
+            LocationSource::Query {
+                name: "geo".to_owned(),
+            },
+        ];
+        let req = request("/?geo=52.2,21.0", &[("Geolocation", "[200, 10]")]);
+
+        assert_eq!(locate_planar(&sources, &req), Some(Point::new(200.0, 10.0)));
+        assert_eq!(
+            locate(
+                &sources,
+                AxisOrder::LonLat,
+                CoordinateSystem::Geographic,
+                &req
+            ),
+            Some(Point::new(21.0, 52.2))
+        );
+    }
+
+    #[test]
+    fn separate_headers() {
+        let source = LocationSource::Headers {
+            lat: "X-Geo-Lat".to_owned(),
+            lon: "X-Geo-Lon".to_owned(),
+        };
+        let req = request("/", &[("X-Geo-Lat", "52.2"), ("X-Geo-Lon", "21.0")]);
+
+        assert_eq!(
+            source.extract(&req, AxisOrder::LonLat),
+            Some(Point::new(21.0, 52.2))
+        );
+    }
+
+    #[test]
+    fn query() {
+        let source = LocationSource::Query {
+            name: "geo".to_owned(),
+        };
+
+        assert_eq!(
+            source.extract(
+                &request("/path?a=1&geo=52.2%2C21.0", &[]),
+                AxisOrder::LonLat
+            ),
+            Some(Point::new(21.0, 52.2))
+        );
+        assert_eq!(
+            source.extract(&request("/path?geo=52.2", &[]), AxisOrder::LonLat),
+            None
+        );
+    }
+
+    #[test]
+    fn cookie() {
+        let source = LocationSource::Cookie {
+            name: "geo".to_owned(),
+        };
+        let req = request("/", &[("Cookie", "session=abc; geo=52.2%2C21.0")]);
+
+        assert_eq!(
+            source.extract(&req, AxisOrder::LonLat),
+            Some(Point::new(21.0, 52.2))
+        );
+    }
+
+    #[test]
+    fn geo_position() {
+        let source = LocationSource::GeoPosition {
+            name: default_geo_position_header(),
+        };
+        let req = request("/", &[("Geo-Position", "52.2;21.0 epu=50")]);
+
+        assert_eq!(
+            source.extract(&req, AxisOrder::LonLat),
+            Some(Point::new(21.0, 52.2))
+        );
+    }
+
+    #[test]
+    fn first_source_wins() {
+        let sources = vec![
+            LocationSource::Query {
+                name: "geo".to_owned(),
+            },
+            LocationSource::JsonHeader {
+                name: "Geolocation".to_owned(),
+            },
+        ];
+
+        let req = request("/?geo=1,2", &[("Geolocation", "[5, 6]")]);
+        assert_eq!(locate_planar(&sources, &req), Some(Point::new(2.0, 1.0)));
+
+        let req = request("/?geo=invalid", &[("Geolocation", "[5, 6]")]);
+        assert_eq!(locate_planar(&sources, &req), Some(Point::new(5.0, 6.0)));
+    }
+}
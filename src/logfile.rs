@@ -0,0 +1,341 @@
+use crate::error::*;
+use log::*;
+
+use chrono::{DateTime, Local};
+use failure::format_err;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+
+// lines waiting to be written, further lines are dropped so a slow disk doesn't hold up requests
+const WRITE_BUFFER_LINES: usize = 16 * 1024;
+
+/// Period of time based log rotation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Rotation {
+    Hourly,
+    Daily,
+}
+
+impl Rotation {
+    /// Identifier of the period of the time, the file is rotated whenever it changes
+    fn period(self, time: DateTime<Local>) -> String {
+        let format = match self {
+            Rotation::Hourly => "%Y%m%d%H",
+            Rotation::Daily => "%Y%m%d",
+        };
+
+        time.format(format).to_string()
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = failure::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "hourly" => Ok(Rotation::Hourly),
+            "daily" => Ok(Rotation::Daily),
+            _ => Err(format_err!("Unknown rotation period {}", value)),
+        }
+    }
+}
+
+/// Parse a size in bytes, with an optional `K`, `M` or `G` suffix
+pub(crate) fn parse_size(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let (number, unit) = match value.char_indices().last() {
+        Some((index, 'K')) | Some((index, 'k')) => (&value[..index], 1 << 10),
+        Some((index, 'M')) | Some((index, 'm')) => (&value[..index], 1 << 20),
+        Some((index, 'G')) | Some((index, 'g')) => (&value[..index], 1 << 30),
+        _ => (value, 1),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(unit))
+        .filter(|size| *size > 0)
+        .ok_or_else(|| format_err!("Invalid size {}", value))
+}
+
+/// When a log file is moved aside and replaced with an empty one
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct RotationPolicy {
+    pub(crate) max_size: Option<u64>,
+    pub(crate) every: Option<Rotation>,
+}
+
+/// Log file rotated according to the policy,
+/// rotated files get the time of rotation appended to their name
+#[derive(Debug)]
+pub(crate) struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    period: Option<String>,
+    policy: RotationPolicy,
+}
+
+fn open_append(path: &Path) -> io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+
+    Ok((file, size))
+}
+
+impl LogFile {
+    pub(crate) fn open(path: impl AsRef<Path>, policy: RotationPolicy) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+        let (file, size) = open_append(&path).map_err(|error| {
+            format_err!("Unable to open log file {}: {}", path.display(), error)
+        })?;
+
+        Ok(Self {
+            path,
+            file,
+            size,
+            period: policy.every.map(|every| every.period(Local::now())),
+            policy,
+        })
+    }
+
+    /// Open the file again, after it was moved by an external tool
+    fn reopen(&mut self) -> io::Result<()> {
+        let (file, size) = open_append(&self.path)?;
+        self.file = file;
+        self.size = size;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self, time: DateTime<Local>) -> io::Result<()> {
+        let suffix = time.format("%Y%m%d-%H%M%S").to_string();
+        let mut rotated = PathBuf::from(format!("{}.{}", self.path.display(), suffix));
+
+        // a file might have been rotated already within the same second
+        let mut count = 0;
+        while rotated.exists() {
+            count += 1;
+            rotated = PathBuf::from(format!("{}.{}.{}", self.path.display(), suffix, count));
+        }
+
+        fs::rename(&self.path, &rotated)?;
+        self.reopen()
+    }
+
+    fn needs_rotation(&self, len: u64, period: Option<&String>) -> bool {
+        // never leave an empty file behind
+        if self.size == 0 {
+            return false;
+        }
+
+        let too_large = self
+            .policy
+            .max_size
+            .map(|max_size| self.size + len > max_size)
+            .unwrap_or(false);
+
+        too_large || period != self.period.as_ref()
+    }
+
+    pub(crate) fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.write_line_at(line, Local::now())
+    }
+
+    fn write_line_at(&mut self, line: &str, time: DateTime<Local>) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        let period = self.policy.every.map(|every| every.period(time));
+
+        if self.needs_rotation(len, period.as_ref()) {
+            self.rotate(time)?;
+        }
+
+        self.period = period;
+        self.file.write_all(format!("{}\n", line).as_bytes())?;
+        self.size += len;
+
+        Ok(())
+    }
+}
+
+enum Message {
+    Line(String),
+    Reopen,
+    Flush(SyncSender<()>),
+}
+
+/// Handle of the thread writing lines to a log file, keeping file I/O off the event loop
+///
+/// Lines are dropped while the buffer of the thread is full.
+#[derive(Debug)]
+pub(crate) struct LogWriter {
+    tx: SyncSender<Message>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl LogWriter {
+    pub(crate) fn spawn(file: LogFile, thread_name: &str) -> Result<Self> {
+        Self::spawn_buffered(file, thread_name, WRITE_BUFFER_LINES)
+    }
+
+    fn spawn_buffered(mut file: LogFile, thread_name: &str, buffer: usize) -> Result<Self> {
+        let (tx, rx) = sync_channel(buffer);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let reported = dropped.clone();
+
+        thread::Builder::new()
+            .name(thread_name.to_owned())
+            .spawn(move || {
+                let mut last_dropped = 0;
+
+                for message in rx {
+                    let dropped = reported.load(Ordering::Relaxed);
+                    if dropped > last_dropped {
+                        warn!(
+                            "Dropped {} lines of log file {}, writing can't keep up",
+                            dropped - last_dropped,
+                            file.path.display()
+                        );
+                        last_dropped = dropped;
+                    }
+
+                    let result = match message {
+                        Message::Line(line) => file.write_line(&line),
+                        Message::Reopen => {
+                            info!("Reopening log file {}", file.path.display());
+                            file.reopen()
+                        }
+                        Message::Flush(done) => {
+                            let _ = done.send(());
+                            continue;
+                        }
+                    };
+
+                    if let Err(error) = result {
+                        error!(
+                            "Unable to write log file {}: {}",
+                            file.path.display(),
+                            error
+                        );
+                    }
+                }
+            })?;
+
+        Ok(Self { tx, dropped })
+    }
+
+    pub(crate) fn write(&self, line: String) {
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(Message::Line(line)) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn reopen(&self) {
+        let _ = self.tx.send(Message::Reopen);
+    }
+
+    /// Wait for the lines sent so far to be written
+    pub(crate) fn flush(&self) {
+        let (done, wait) = sync_channel(1);
+
+        if self.tx.send(Message::Flush(done)).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    use crate::testing::temp_dir;
+
+    #[test]
+    fn size() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("10K").unwrap(), 10 * 1024);
+        assert_eq!(parse_size("100m").unwrap(), 100 * 1024 * 1024);
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("ten").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn size_rotation() {
+        let dir = temp_dir();
+        let path = dir.path().join("access.log");
+
+        let policy = RotationPolicy {
+            max_size: Some(10),
+            every: None,
+        };
+        let mut file = LogFile::open(&path, policy).unwrap();
+
+        file.write_line("first").unwrap();
+        file.write_line("second").unwrap();
+        file.write_line("third").unwrap();
+
+        let mut rotated = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|entry| entry != &path)
+            .map(|entry| fs::read_to_string(entry).unwrap())
+            .collect::<Vec<_>>();
+        rotated.sort();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third\n");
+        assert_eq!(rotated, vec!["first\n", "second\n"]);
+    }
+
+    #[test]
+    fn time_rotation() {
+        let dir = temp_dir();
+        let path = dir.path().join("access.log");
+        let time = |hour, minute| {
+            Local
+                .with_ymd_and_hms(2019, 6, 19, hour, minute, 0)
+                .unwrap()
+        };
+
+        let policy = RotationPolicy {
+            max_size: None,
+            every: Some(Rotation::Hourly),
+        };
+        let mut file = LogFile::open(&path, policy).unwrap();
+
+        file.write_line_at("first", time(10, 0)).unwrap();
+        file.write_line_at("second", time(10, 59)).unwrap();
+        file.write_line_at("third", time(11, 0)).unwrap();
+
+        let rotated = path.with_file_name("access.log.20190619-110000");
+        assert_eq!(fs::read_to_string(&rotated).unwrap(), "first\nsecond\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third\n");
+    }
+
+    #[test]
+    fn full_buffer() {
+        let dir = temp_dir();
+        let path = dir.path().join("access.log");
+        let file = LogFile::open(&path, RotationPolicy::default()).unwrap();
+        let writer = LogWriter::spawn_buffered(file, "log-writer-test", 4).unwrap();
+
+        // hold the thread up until the lines are sent
+        let (done, wait) = sync_channel(0);
+        writer.tx.send(Message::Flush(done)).unwrap();
+        (0..10).for_each(|line| writer.write(line.to_string()));
+        wait.recv().unwrap();
+        writer.flush();
+
+        let dropped = writer.dropped.load(Ordering::Relaxed);
+        let written = fs::read_to_string(&path).unwrap().lines().count();
+        assert!(dropped >= 6, "{} lines dropped", dropped);
+        assert_eq!(written + dropped, 10);
+    }
+}
@@ -1,7 +1,42 @@
-use chrono::Local;
+use crate::error::*;
+
+use chrono::{Local, SecondsFormat};
 use env_logger::fmt::{Color, Style, StyledValue};
+use failure::format_err;
 use log::Level;
+use serde_json::json;
 use std::io::Write;
+use std::str::FromStr;
+
+/// Target of access log records, already serialized by the access log in the JSON format,
+/// distinct from every module path so other records of the access module aren't mistaken for them
+pub(crate) static ACCESS_TARGET: &str = "access";
+
+/// Format of the log output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum LogFormat {
+    /// Colored, human-readable lines
+    Text,
+    /// A JSON object per line
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = failure::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format_err!("Unknown log format {}", value)),
+        }
+    }
+}
+
+/// Timestamp of JSON log records
+pub(crate) fn json_timestamp() -> String {
+    Local::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
 
 fn colored_level<'a>(style: &'a mut Style, level: Level) -> StyledValue<'a, &'static str> {
     match level {
@@ -13,10 +48,13 @@ fn colored_level<'a>(style: &'a mut Style, level: Level) -> StyledValue<'a, &'st
     }
 }
 
-pub(crate) fn init_logger() {
-    let env = env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "geoproxy=info");
-    env_logger::Builder::from_env(env)
-        .format(|f, record| {
+pub(crate) fn init_logger(format: LogFormat) {
+    let env = env_logger::Env::default()
+        .filter_or(env_logger::DEFAULT_FILTER_ENV, "geoproxy=info,access=info");
+    let mut builder = env_logger::Builder::from_env(env);
+
+    match format {
+        LogFormat::Text => builder.format(|f, record| {
             let mut style = f.style();
             let level = colored_level(&mut style, record.level());
 
@@ -27,6 +65,22 @@ pub(crate) fn init_logger() {
                 level,
                 record.args(),
             )
-        })
-        .init();
+        }),
+        LogFormat::Json => builder.format(|f, record| {
+            if record.target() == ACCESS_TARGET {
+                return writeln!(f, "{}", record.args());
+            }
+
+            let line = json!({
+                "timestamp": json_timestamp(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+
+            writeln!(f, "{}", line)
+        }),
+    };
+
+    builder.init();
 }
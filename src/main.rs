@@ -1,26 +1,63 @@
 use crate::error::*;
 use log::*;
 
+use arc_swap::ArcSwap;
+use failure::format_err;
 use hyper::{
     rt::{self, Future},
-    service::service_fn,
-    Client, Method, Server, StatusCode,
+    server::conn::{AddrIncoming, AddrStream},
+    service::{make_service_fn, service_fn},
+    Server,
 };
-use std::net::ToSocketAddrs;
+use std::net::{IpAddr, ToSocketAddrs};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::Duration;
+use tokio::runtime::Runtime;
 
+use crate::access::AccessLog;
+use crate::admin::Admin;
+use crate::check::check_config;
 use crate::cli::setup_cli;
 use crate::config::read_config;
+use crate::coverage::coverage_report;
+use crate::geoip::{is_trusted, GeoIp};
+use crate::headers::Peer;
+use crate::health::HealthChecker;
+use crate::logfile::{parse_size, LogFile, LogWriter, RotationPolicy};
 use crate::logger::init_logger;
 use crate::metrics::*;
-use crate::util::{error_result, setup_index};
+use crate::proxy::Proxy;
+use crate::reload::ConfigReloader;
+use crate::shutdown::{drain, Shutdown, Stopped};
+use crate::state::ProxyState;
+use crate::tls::{TlsAcceptor, TlsConfig, TlsIncoming, TlsStream};
 
+mod access;
+mod admin;
+mod area;
+mod balance;
+mod check;
 mod cli;
 mod config;
+mod connector;
+mod coverage;
 mod error;
+mod features;
+mod geoip;
+mod headers;
+mod health;
+mod index;
+mod location;
+mod logfile;
 mod logger;
 mod metrics;
+mod proxy;
+mod reload;
+mod shutdown;
+mod state;
+#[cfg(test)]
+mod testing;
+mod tls;
 mod util;
 
 fn main() -> Result<()> {
@@ -32,111 +69,218 @@ fn main() -> Result<()> {
         .to_socket_addrs()?
         .next()
         .unwrap();
+    let admin_addr = args
+        .value_of("admin_address")
+        .map(|value| value.to_socket_addrs().unwrap().next().unwrap());
     let metrics_addr = args
         .value_of("statsd")
         .map(|value| value.to_socket_addrs().unwrap().next().unwrap());
-    let config = args.value_of("config").unwrap();
+    let config_path = args.value_of("config").unwrap();
+    let precision = args
+        .value_of("coordinate_precision")
+        .map(str::parse)
+        .transpose()?;
+    let trusted_proxies: Arc<Vec<_>> = Arc::new(
+        args.values_of("trusted_proxy")
+            .map(|values| values.map(|value| value.parse().unwrap()).collect())
+            .unwrap_or_default(),
+    );
+
+    let drain_timeout = Duration::from_secs(args.value_of("drain_timeout").unwrap().parse()?);
+
+    let log_format = args.value_of("log_format").unwrap().parse()?;
+
+    init_logger(log_format);
+
+    if let Some(check) = args.subcommand_matches("check-config") {
+        let max_overlap = check.value_of("max_overlap").unwrap().parse()?;
 
-    init_logger();
+        return check_config(config_path, precision, max_overlap);
+    }
+
+    if let Some(coverage) = args.subcommand_matches("coverage") {
+        return coverage_report(config_path, precision, coverage.value_of("region").unwrap());
+    }
 
     // setup metrics
-    let metrics = setup_metrics(metrics_addr)?;
-
-    let config = read_config(config)?;
-    let index = Arc::new(setup_index(config));
-
-    let client = Client::new();
-
-    let proxy_service = move || {
-        let index = index.clone();
-        let client = client.clone();
-        let metrics = metrics.clone();
-
-        service_fn(
-            move |mut req| -> Box<dyn Future<Item = _, Error = _> + Send> {
-                // request time span measure
-                let span = Instant::now();
-
-                match req.method() {
-                    &Method::GET => {
-                        // Geolocation header
-                        let location = req
-                            .headers()
-                            .get("Geolocation")
-                            .map(|value| value.to_str().ok())
-                            .and_then(|value| value)
-                            .map(|value| serde_json::from_str(value).ok())
-                            .and_then(|value| value);
-
-                        // backend by provided geolocation
-                        let backend = index.lookup_coords(location.as_ref());
-
-                        // rewrite url
-                        let mapped_uri = backend.map_url(req.uri());
-                        let orig_uri = std::mem::replace(req.uri_mut(), mapped_uri);
-
-                        let backend = format!("{}", backend);
-
-                        Box::new(
-                            client
-                                .request(req)
-                                .and_then({
-                                    let metrics = metrics.clone();
-                                    let orig_uri = orig_uri.clone();
-
-                                    move |resp| {
-                                        let elapsed = span.elapsed();
-
-                                        info!(
-                                            "{} GET {} [via: {}, loc: {:?}] {:?}",
-                                            resp.status().as_str(),
-                                            orig_uri,
-                                            backend,
-                                            location,
-                                            elapsed
-                                        );
-
-                                        let _ = metrics.incr("requests.proxied");
-                                        let _ = metrics.time_duration("request.duration", elapsed);
-
-                                        Ok(resp)
-                                    }
-                                })
-                                .or_else({
-                                    let metrics = metrics.clone();
-                                    move |_error| {
-                                        error_result(
-                                            StatusCode::BAD_GATEWAY,
-                                            Method::GET,
-                                            orig_uri,
-                                            metrics.clone(),
-                                            span,
-                                            "requests.failed",
-                                        )
-                                    }
-                                }),
-                        )
-                    }
-                    method => error_result(
-                        StatusCode::METHOD_NOT_ALLOWED,
-                        method.clone(),
-                        req.uri().path_and_query(),
-                        metrics.clone(),
-                        span,
-                        "requests.rejected",
-                    ),
-                }
-            },
-        )
+    let metrics = setup_metrics(metrics_addr, args.value_of("metric_tags").unwrap().parse()?)?;
+
+    let geoip = args
+        .value_of("geoip_db")
+        .map(|path| GeoIp::open(path, trusted_proxies.to_vec()).map(Arc::new))
+        .transpose()?;
+
+    let access_log = match args.value_of("access_log") {
+        Some(path) => {
+            let policy = RotationPolicy {
+                max_size: args
+                    .value_of("access_log_max_size")
+                    .map(parse_size)
+                    .transpose()?,
+                every: args
+                    .value_of("access_log_rotate")
+                    .map(str::parse)
+                    .transpose()?,
+            };
+            let writer = LogWriter::spawn(LogFile::open(path, policy)?, "access-log")?;
+            let format = args.value_of("access_log_format").unwrap().parse()?;
+
+            AccessLog::to_file(writer, format)
+        }
+        None => AccessLog::new(log_format),
     };
+    let access_log = Arc::new(access_log);
+    let access_log_reopener = access_log.clone().watch();
+    let final_access_log = access_log.clone();
+    let final_metrics = metrics.clone();
+
+    let config = read_config(config_path, precision)?;
+
+    // certificates given on the command line take precedence over the config
+    let tls = match (args.value_of("tls_cert"), args.value_of("tls_key")) {
+        (Some(cert), Some(key)) => Some(TlsConfig::single(cert, key)),
+        _ => config.tls.clone(),
+    };
+    let acceptor = tls
+        .map(|tls| TlsAcceptor::new(tls, metrics.clone()).map(Arc::new))
+        .transpose()?;
+    let tls_reloader = acceptor
+        .clone()
+        .map(|acceptor| acceptor.watch())
+        .transpose()?;
+
+    let state = Arc::new(ArcSwap::from_pointee(ProxyState::from(config)));
+
+    let checker = HealthChecker::new(metrics.clone());
+    let reloader = ConfigReloader::new(
+        config_path,
+        precision,
+        state.clone(),
+        checker.clone(),
+        metrics.clone(),
+    )
+    .watch()?;
+
+    // initial health checks, spawned once the runtime is up
+    let initial_state = state.clone();
+
+    let shutdown = Arc::new(Shutdown::default());
+    let signal = shutdown.clone().signal().shared();
+    let server_signal = signal.clone().map(|_| ()).map_err(|_| ());
+
+    let admin_server = match admin_addr {
+        Some(admin_addr) => {
+            let admin = Arc::new(Admin::new(state.clone(), metrics.clone(), shutdown.clone()));
+            let server = Server::try_bind(&admin_addr)?
+                .serve(move || {
+                    let admin = admin.clone();
+
+                    service_fn(move |req| admin.handle(req))
+                })
+                .map_err(|e| error!("admin server error: {}", e));
+
+            info!("Admin endpoints listening on {}", admin_addr);
+
+            Some(server)
+        }
+        None => None,
+    };
+
+    let proxy_service = move |addr: IpAddr, proto: &'static str| {
+        let peer = Peer {
+            addr,
+            proto,
+            trusted: is_trusted(&trusted_proxies, addr),
+        };
+        let proxy = Proxy::new(
+            state.clone(),
+            metrics.clone(),
+            access_log.clone(),
+            geoip.clone(),
+            peer,
+        );
+
+        service_fn(move |req| proxy.handle(req))
+    };
+
+    let server: Box<dyn Future<Item = (), Error = ()> + Send> = match acceptor {
+        Some(acceptor) => {
+            let incoming = TlsIncoming::new(AddrIncoming::bind(&bind_addr)?, acceptor);
+
+            info!("Listening on {} (TLS)", bind_addr);
+
+            Box::new(
+                Server::builder(incoming)
+                    .serve(make_service_fn(move |conn: &TlsStream<AddrStream>| {
+                        proxy_service(conn.get_ref().get_ref().remote_addr().ip(), "https")
+                    }))
+                    .with_graceful_shutdown(server_signal)
+                    .map_err(|e| error!("server error: {}", e)),
+            )
+        }
+        None => {
+            info!("Listening on {}", bind_addr);
+
+            Box::new(
+                Server::bind(&bind_addr)
+                    .serve(make_service_fn(move |conn: &AddrStream| {
+                        proxy_service(conn.remote_addr().ip(), "http")
+                    }))
+                    .with_graceful_shutdown(server_signal)
+                    .map_err(|e| error!("server error: {}", e)),
+            )
+        }
+    };
+
+    let drain_signal = signal.map(|_| ()).map_err(|_| ());
+
+    let mut runtime = Runtime::new()?;
+    let stopped = runtime.block_on(rt::lazy(move || {
+        checker.spawn(&initial_state.load().index);
+        rt::spawn(reloader);
+
+        if let Some(tls_reloader) = tls_reloader {
+            rt::spawn(tls_reloader);
+        }
+
+        if let Some(access_log_reopener) = access_log_reopener {
+            rt::spawn(access_log_reopener);
+        }
+
+        if let Some(admin_server) = admin_server {
+            rt::spawn(admin_server);
+        }
+
+        drain(server, drain_signal, drain_timeout)
+    }));
 
-    let server = Server::bind(&bind_addr)
-        .serve(proxy_service)
-        .map_err(|e| error!("server error: {}", e));
+    let stopped = stopped.unwrap_or(Stopped::Failed);
 
-    info!("Listening on {}", bind_addr);
+    match stopped {
+        Stopped::Drained => {
+            info!("Connections drained, shutting down");
+            final_metrics.incr("shutdown.drained");
+        }
+        Stopped::TimedOut => {
+            warn!(
+                "Connections not drained within {}s, shutting down",
+                drain_timeout.as_secs()
+            );
+            final_metrics.incr("shutdown.drain_timeout");
+        }
+        Stopped::Failed => {
+            error!("Server failed, shutting down");
+            final_metrics.incr("shutdown.failed");
+        }
+    }
 
-    rt::run(server);
+    // statsd metrics are sent unbuffered as they're recorded, only the access log file has a queue
+    final_access_log.flush();
+    runtime.shutdown_now().wait().ok();
 
-    Ok(())
+    if stopped == Stopped::Failed {
+        Err(format_err!("Server failed"))
+    } else {
+        Ok(())
+    }
 }
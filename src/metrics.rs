@@ -1,18 +1,267 @@
 use crate::error::*;
-#[allow(unused_imports)]
-pub(crate) use cadence::prelude::*;
+use cadence::prelude::*;
 use cadence::{NopMetricSink, StatsdClient, UdpMetricSink};
+use failure::format_err;
+use http::{Method, StatusCode};
 use log::error;
 use log::info;
+use prometheus::{
+    histogram_opts, opts, Encoder, HistogramVec, IntCounterVec, Registry, TextEncoder,
+};
 use std::fmt::Display;
 use std::net::{ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 static STATSD_ROOT: &str = "geoproxy";
+static PROMETHEUS_PREFIX: &str = "geoproxy";
 
-pub(crate) type MetricsClient = Arc<StatsdClient>;
+// labels of the Prometheus request metrics
+static REQUEST_LABELS: &[&str] = &["backend", "status", "method"];
 
-pub(crate) fn setup_metrics(host: Option<impl ToSocketAddrs + Display>) -> Result<MetricsClient> {
+pub(crate) type MetricsClient = Arc<Metrics>;
+
+/// Sanitize a value to be used as a single segment of a metric name
+pub(crate) fn metric_segment(value: &str) -> String {
+    value
+        .trim_end_matches('/')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// How statsd metrics are broken down by backend, status class and method
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TagFormat {
+    /// Dimensions appended to the metric name, e.g. `requests.proxied.europe.2xx.GET`
+    Dotted,
+    /// DogStatsD tags, e.g. `requests.proxied|#backend:europe,status:2xx,method:GET`
+    DogStatsd,
+}
+
+impl FromStr for TagFormat {
+    type Err = failure::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "dotted" => Ok(TagFormat::Dotted),
+            "dogstatsd" => Ok(TagFormat::DogStatsd),
+            _ => Err(format_err!("Unknown metric tag format {}", value)),
+        }
+    }
+}
+
+/// Name of a metric with the tag values appended as segments
+fn dotted_name(metric: &str, tags: &[(&str, &str)]) -> String {
+    tags.iter().fold(metric.to_owned(), |name, (_, value)| {
+        format!("{}.{}", name, metric_segment(value))
+    })
+}
+
+/// Class of the status code, e.g. `2xx`
+fn status_class(status: StatusCode) -> String {
+    format!("{}xx", status.as_u16() / 100)
+}
+
+/// How a request was answered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Outcome {
+    /// Response of an upstream
+    Proxied,
+    /// Method not allowed by the backend
+    Rejected,
+    /// Upstream request failed
+    Failed,
+    /// Upstream did not respond in time
+    Timeout,
+}
+
+impl Outcome {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Outcome::Proxied => "proxied",
+            Outcome::Rejected => "rejected",
+            Outcome::Failed => "failed",
+            Outcome::Timeout => "timeout",
+        }
+    }
+
+    fn metric(self) -> &'static str {
+        match self {
+            Outcome::Proxied => "requests.proxied",
+            Outcome::Rejected => "requests.rejected",
+            Outcome::Failed => "requests.failed",
+            Outcome::Timeout => "requests.timeout",
+        }
+    }
+}
+
+/// Metrics sent to statsd, with request metrics also collected for Prometheus
+pub(crate) struct Metrics {
+    statsd: StatsdClient,
+    format: TagFormat,
+    registry: Registry,
+    proxied: IntCounterVec,
+    rejected: IntCounterVec,
+    failed: IntCounterVec,
+    fallback: IntCounterVec,
+    duration: HistogramVec,
+}
+
+impl Metrics {
+    pub(crate) fn new(statsd: StatsdClient, format: TagFormat) -> Result<Self> {
+        let registry = Registry::new_custom(Some(PROMETHEUS_PREFIX.to_owned()), None)?;
+
+        let counter = |name: &str, help: &str, labels: &[&str]| -> Result<IntCounterVec> {
+            let counter = IntCounterVec::new(opts!(name, help), labels)?;
+            registry.register(Box::new(counter.clone()))?;
+
+            Ok(counter)
+        };
+
+        let proxied = counter(
+            "requests_proxied_total",
+            "Requests answered by an upstream",
+            REQUEST_LABELS,
+        )?;
+        let rejected = counter(
+            "requests_rejected_total",
+            "Requests with a method not allowed by the backend",
+            REQUEST_LABELS,
+        )?;
+        let failed = counter(
+            "requests_failed_total",
+            "Requests failed or timed out on the upstreams",
+            REQUEST_LABELS,
+        )?;
+        let fallback = counter(
+            "requests_default_total",
+            "Requests routed to the default backend",
+            &["reason", "method"],
+        )?;
+
+        let duration = HistogramVec::new(
+            histogram_opts!("request_duration_seconds", "Time taken to answer requests"),
+            REQUEST_LABELS,
+        )?;
+        registry.register(Box::new(duration.clone()))?;
+
+        Ok(Self {
+            statsd,
+            format,
+            registry,
+            proxied,
+            rejected,
+            failed,
+            fallback,
+            duration,
+        })
+    }
+
+    pub(crate) fn incr(&self, metric: &str) {
+        let _ = self.statsd.incr(metric);
+    }
+
+    pub(crate) fn gauge(&self, metric: &str, value: u64) {
+        let _ = self.statsd.gauge(metric, value);
+    }
+
+    /// Statsd counter broken down by the tags
+    fn incr_tagged(&self, metric: &str, tags: &[(&str, &str)]) {
+        match self.format {
+            TagFormat::Dotted => self.incr(&dotted_name(metric, tags)),
+            TagFormat::DogStatsd => {
+                let _ = tags
+                    .iter()
+                    .fold(
+                        self.statsd.incr_with_tags(metric),
+                        |builder, (key, value)| builder.with_tag(key, value),
+                    )
+                    .try_send();
+            }
+        }
+    }
+
+    /// Statsd timer broken down by the tags
+    fn time_tagged(&self, metric: &str, tags: &[(&str, &str)], elapsed: Duration) {
+        match self.format {
+            TagFormat::Dotted => {
+                let _ = self
+                    .statsd
+                    .time_duration(&dotted_name(metric, tags), elapsed);
+            }
+            TagFormat::DogStatsd => {
+                let _ = tags
+                    .iter()
+                    .fold(
+                        self.statsd.time_duration_with_tags(metric, elapsed),
+                        |builder, (key, value)| builder.with_tag(key, value),
+                    )
+                    .try_send();
+            }
+        }
+    }
+
+    /// Record a request answered by the proxy
+    pub(crate) fn request(
+        &self,
+        outcome: Outcome,
+        backend: &str,
+        method: &Method,
+        status: StatusCode,
+        elapsed: Duration,
+    ) {
+        let class = status_class(status);
+        let tags = [
+            ("backend", backend),
+            ("status", class.as_str()),
+            ("method", method.as_str()),
+        ];
+
+        self.incr_tagged(outcome.metric(), &tags);
+
+        if outcome == Outcome::Proxied {
+            self.time_tagged("request.duration", &tags, elapsed);
+        }
+
+        let labels = [backend, status.as_str(), method.as_str()];
+        let counter = match outcome {
+            Outcome::Proxied => &self.proxied,
+            Outcome::Rejected => &self.rejected,
+            Outcome::Failed | Outcome::Timeout => &self.failed,
+        };
+
+        counter.with_label_values(&labels).inc();
+        self.duration
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Record a request routed to the default backend, e.g. for the lack of location
+    pub(crate) fn fallback(&self, reason: &str, method: &Method) {
+        self.incr_tagged(
+            "requests.default",
+            &[("reason", reason), ("method", method.as_str())],
+        );
+        self.fallback
+            .with_label_values(&[reason, method.as_str()])
+            .inc();
+    }
+
+    /// Collected metrics in the Prometheus text format
+    pub(crate) fn export(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+
+        Ok(buffer)
+    }
+}
+
+pub(crate) fn setup_metrics(
+    host: Option<impl ToSocketAddrs + Display>,
+    format: TagFormat,
+) -> Result<MetricsClient> {
     let client = if let Some(host) = host {
         let socket = UdpSocket::bind("0.0.0.0:0")?;
         socket.set_nonblocking(true)?;
@@ -28,5 +277,62 @@ pub(crate) fn setup_metrics(host: Option<impl ToSocketAddrs + Display>) -> Resul
     .with_error_handler(|error| error!("Metric error: {}", error))
     .build();
 
-    Ok(Arc::new(client))
+    Ok(Arc::new(Metrics::new(client, format)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export() {
+        let metrics = Metrics::new(
+            StatsdClient::from_sink("test", NopMetricSink),
+            TagFormat::Dotted,
+        )
+        .unwrap();
+
+        metrics.request(
+            Outcome::Proxied,
+            "europe",
+            &Method::GET,
+            StatusCode::OK,
+            Duration::from_millis(20),
+        );
+        metrics.request(
+            Outcome::Timeout,
+            "default",
+            &Method::GET,
+            StatusCode::GATEWAY_TIMEOUT,
+            Duration::from_secs(1),
+        );
+        metrics.fallback("no_location", &Method::GET);
+
+        let exported = String::from_utf8(metrics.export().unwrap()).unwrap();
+
+        assert!(exported.contains(
+            "geoproxy_requests_proxied_total{backend=\"europe\",method=\"GET\",status=\"200\"} 1"
+        ));
+        assert!(exported.contains(
+            "geoproxy_requests_failed_total{backend=\"default\",method=\"GET\",status=\"504\"} 1"
+        ));
+        assert!(exported.contains(
+            "geoproxy_request_duration_seconds_count{backend=\"europe\",method=\"GET\",status=\"200\"} 1"
+        ));
+        assert!(exported
+            .contains("geoproxy_requests_default_total{method=\"GET\",reason=\"no_location\"} 1"));
+    }
+
+    #[test]
+    fn dotted() {
+        let tags = [
+            ("backend", "http://10.0.0.1/"),
+            ("status", &*status_class(StatusCode::NOT_FOUND)),
+        ];
+
+        assert_eq!(
+            dotted_name("requests.proxied", &tags),
+            "requests.proxied.http___10_0_0_1.4xx"
+        );
+    }
 }
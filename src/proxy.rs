@@ -0,0 +1,621 @@
+use log::*;
+
+use futures::future::{self, Future};
+use geo_types::Point;
+use geoindex::LookupReason;
+use hyper::{
+    body::Payload,
+    header::{HeaderMap, ALLOW},
+    Body, Error as HyperError, Method, Request, Response, StatusCode, Uri, Version,
+};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::timer::Timeout;
+
+use crate::access::{AccessLog, AccessRecord};
+use crate::config::Backend;
+use crate::geoip::GeoIp;
+use crate::headers::Peer;
+use crate::location::locate;
+use crate::metrics::*;
+use crate::state::{ProxyState, SharedState};
+use crate::util::error_result;
+
+pub(crate) type ResponseFuture = Box<dyn Future<Item = Response<Body>, Error = HyperError> + Send>;
+
+/// Handles requests of a single client connection
+pub(crate) struct Proxy {
+    state: SharedState,
+    metrics: MetricsClient,
+    access_log: Arc<AccessLog>,
+    geoip: Option<Arc<GeoIp>>,
+    peer: Peer,
+}
+
+impl Proxy {
+    pub(crate) fn new(
+        state: SharedState,
+        metrics: MetricsClient,
+        access_log: Arc<AccessLog>,
+        geoip: Option<Arc<GeoIp>>,
+        peer: Peer,
+    ) -> Self {
+        Self {
+            state,
+            metrics,
+            access_log,
+            geoip,
+            peer,
+        }
+    }
+
+    pub(crate) fn handle(&self, mut req: Request<Body>) -> ResponseFuture {
+        // request time span measure
+        let span = Instant::now();
+
+        let state = self.state.load_full();
+
+        // request location, from the first source providing it,
+        // the client address is looked up as a last resort
+        let location = locate(
+            &state.location,
+            state.axis_order,
+            state.index.coordinate_system(),
+            &req,
+        )
+        .or_else(|| {
+            let location = self.geoip.as_ref()?.locate(self.peer.addr, &req)?;
+            self.metrics.incr("location.geoip");
+
+            Some(location)
+        });
+
+        // backend by provided geolocation
+        let (backend, reason) = state
+            .index
+            .explain_by(location.as_ref(), Backend::is_healthy);
+
+        if std::ptr::eq(backend, state.index.default()) {
+            self.metrics.fallback(fallback_reason(reason), req.method());
+        }
+
+        let record = AccessRecord::new(&req, self.peer.addr, location, span);
+
+        if !backend.allows_method(req.method()) {
+            let allow = backend.allow_header();
+            let rejected = error_result(
+                StatusCode::METHOD_NOT_ALLOWED,
+                record.backend(state.backend_label(backend)),
+                self.access_log.clone(),
+                self.metrics.clone(),
+                Outcome::Rejected,
+            );
+
+            return Box::new(rejected.map(move |mut resp| {
+                if let Some(allow) = allow {
+                    resp.headers_mut().insert(ALLOW, allow);
+                }
+
+                resp
+            }));
+        }
+
+        state.headers.rewrite_request(&mut req, self.peer);
+
+        // only requests without a body can be replayed
+        let retries = if req.method().is_idempotent() && req.body().is_end_stream() {
+            state.retries
+        } else {
+            0
+        };
+
+        let (parts, body) = req.into_parts();
+
+        Arc::new(Forward {
+            state,
+            metrics: self.metrics.clone(),
+            access_log: self.access_log.clone(),
+            record,
+            method: parts.method,
+            uri: parts.uri,
+            version: parts.version,
+            headers: parts.headers,
+            location,
+            retries,
+        })
+        .send(0, body)
+    }
+}
+
+/// Why a request ended up with the default backend
+fn fallback_reason(reason: LookupReason<f64>) -> &'static str {
+    match reason {
+        LookupReason::NoLocation => "no_location",
+        LookupReason::Outside { skipped } if skipped > 0 => "unhealthy",
+        _ => "outside",
+    }
+}
+
+/// Reason of an unsuccessful upstream request
+enum Failure {
+    Timeout,
+    Error(String),
+}
+
+impl From<HyperError> for Failure {
+    fn from(error: HyperError) -> Self {
+        // connect timeouts are reported by the connector as I/O errors
+        let timed_out = std::error::Error::source(&error)
+            .and_then(|cause| cause.downcast_ref::<std::io::Error>())
+            .map(|cause| cause.kind() == std::io::ErrorKind::TimedOut)
+            .unwrap_or(false);
+
+        if timed_out {
+            Failure::Timeout
+        } else {
+            Failure::Error(error.to_string())
+        }
+    }
+}
+
+/// Request forwarded to the upstreams, retried with the following backends
+/// of the lookup chain in case of failures
+struct Forward {
+    state: Arc<ProxyState>,
+    metrics: MetricsClient,
+    access_log: Arc<AccessLog>,
+    /// Access record of the request, completed once it is answered
+    record: AccessRecord,
+    method: Method,
+    uri: Uri,
+    version: Version,
+    headers: HeaderMap,
+    location: Option<Point<f64>>,
+    retries: usize,
+}
+
+impl Forward {
+    /// Backend handling the given attempt, skipping unhealthy backends
+    /// and the ones not accepting the request method
+    fn backend(&self, attempt: usize) -> Option<&Backend> {
+        self.state
+            .index
+            .lookup_chain_by(self.location.as_ref(), Backend::is_healthy)
+            .into_iter()
+            .filter(|backend| backend.allows_method(&self.method))
+            .nth(attempt)
+    }
+
+    fn send(self: Arc<Self>, attempt: usize, body: Body) -> ResponseFuture {
+        let backend = match self.backend(attempt) {
+            Some(backend) => backend,
+            None => {
+                return error_result(
+                    StatusCode::BAD_GATEWAY,
+                    self.record.clone(),
+                    self.access_log.clone(),
+                    self.metrics.clone(),
+                    Outcome::Failed,
+                )
+            }
+        };
+
+        // rewrite url
+        let upstream = backend.select_upstream(&self.headers);
+        let upstream_uri = upstream.map_url(&self.uri);
+
+        let mut req = Request::new(body);
+        *req.method_mut() = self.method.clone();
+        *req.uri_mut() = upstream_uri.clone();
+        *req.version_mut() = self.version;
+        *req.headers_mut() = self.headers.clone();
+
+        let upstream_name = format!("{}", upstream);
+        let upstream_metric = format!("upstream.{}", metric_segment(upstream.url().as_str()));
+        let outstanding = upstream.start_request();
+        self.metrics.incr(&format!("{}.requests", upstream_metric));
+
+        let region = self.state.region(backend);
+        let record = self
+            .record
+            .clone()
+            .backend(self.state.backend_label(backend))
+            .upstream(upstream_name.clone(), &upstream_uri);
+        let retry = attempt < self.retries && self.backend(attempt + 1).is_some();
+
+        let request = backend.client().request(req).map_err(Failure::from);
+        let request: Box<dyn Future<Item = _, Error = _> + Send> = match backend.response_timeout()
+        {
+            Some(timeout) => Box::new(Timeout::new(request, timeout).map_err(|error| {
+                if error.is_elapsed() {
+                    Failure::Timeout
+                } else {
+                    error
+                        .into_inner()
+                        .unwrap_or_else(|| Failure::Error("timer error".to_owned()))
+                }
+            })),
+            None => Box::new(request),
+        };
+
+        Box::new(request.then(move |result| -> ResponseFuture {
+            drop(outstanding);
+
+            let (status, outcome) = match result {
+                Ok(resp) => {
+                    return Box::new(future::ok(self.respond(resp, record, region)));
+                }
+                Err(Failure::Timeout) => (StatusCode::GATEWAY_TIMEOUT, Outcome::Timeout),
+                Err(Failure::Error(error)) => {
+                    debug!("Request to {} failed: {}", upstream_name, error);
+                    (StatusCode::BAD_GATEWAY, Outcome::Failed)
+                }
+            };
+
+            self.metrics
+                .incr(&format!("{}.{}", upstream_metric, outcome.name()));
+
+            if retry {
+                warn!(
+                    "{} {} {} on {}, retrying",
+                    self.method,
+                    self.uri,
+                    outcome.name(),
+                    upstream_name
+                );
+                self.metrics.incr("requests.retried");
+
+                self.send(attempt + 1, Body::empty())
+            } else {
+                error_result(
+                    status,
+                    record,
+                    self.access_log.clone(),
+                    self.metrics.clone(),
+                    outcome,
+                )
+            }
+        }))
+    }
+
+    fn respond(
+        &self,
+        mut resp: Response<Body>,
+        record: AccessRecord,
+        region: Option<String>,
+    ) -> Response<Body> {
+        self.state
+            .headers
+            .rewrite_response(&mut resp, record.upstream_name(), region.as_deref());
+
+        let record = record.response(resp.status());
+
+        self.metrics.request(
+            Outcome::Proxied,
+            record.backend_label(),
+            &self.method,
+            resp.status(),
+            record.elapsed(),
+        );
+
+        resp.map(|body| self.access_log.clone().log_body(record, body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arc_swap::ArcSwap;
+    use futures::Stream;
+    use net2::TcpBuilder;
+    use serde_json::json;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+    use tokio::runtime::Runtime;
+
+    use crate::config::read_config;
+    use crate::logfile::{LogFile, LogWriter, RotationPolicy};
+    use crate::logger::LogFormat;
+    use crate::testing::{metrics, temp_dir, write_config, write_geoip_db};
+
+    fn state(config: serde_json::Value) -> SharedState {
+        let dir = temp_dir();
+        let config = read_config(write_config(&dir, &config), None).unwrap();
+
+        Arc::new(ArcSwap::from_pointee(ProxyState::from(config)))
+    }
+
+    fn peer() -> Peer {
+        Peer {
+            addr: "127.0.0.1".parse().unwrap(),
+            proto: "http",
+            trusted: false,
+        }
+    }
+
+    fn proxy(config: serde_json::Value) -> Proxy {
+        Proxy::new(
+            state(config),
+            setup_metrics(None::<&str>, TagFormat::Dotted).unwrap(),
+            Arc::new(AccessLog::new(LogFormat::Text)),
+            None,
+            peer(),
+        )
+    }
+
+    /// Upstream answering every request with an empty 200 response, along with its request count
+    fn upstream() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut buffer = [0; 1024];
+
+                if stream.read(&mut buffer).unwrap_or(0) > 0 {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    stream
+                        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                        .ok();
+                }
+            }
+        });
+
+        (url, requests)
+    }
+
+    /// Upstream answering every request with a chunked body of 11 bytes
+    fn chunked_upstream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut buffer = [0; 1024];
+
+                if stream.read(&mut buffer).unwrap_or(0) > 0 {
+                    stream
+                        .write_all(
+                            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                              5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+                        )
+                        .ok();
+                }
+            }
+        });
+
+        url
+    }
+
+    /// Upstream accepting connections without ever answering
+    fn hung_upstream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        thread::spawn(move || listener.incoming().collect::<Vec<_>>());
+
+        url
+    }
+
+    /// Listener with a full accept queue, further connections to it are never established
+    struct BlackHole {
+        url: String,
+        _sockets: (TcpListener, Vec<TcpStream>),
+    }
+
+    fn black_hole() -> BlackHole {
+        let listener = TcpBuilder::new_v4()
+            .unwrap()
+            .bind("127.0.0.1:0")
+            .unwrap()
+            .listen(0)
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        let queued = (0..2)
+            .filter_map(|_| TcpStream::connect_timeout(&addr, Duration::from_millis(100)).ok())
+            .collect();
+
+        BlackHole {
+            url: format!("http://{}", addr),
+            _sockets: (listener, queued),
+        }
+    }
+
+    /// Config with an area around the request location served by an unreachable upstream,
+    /// and the given default upstream
+    fn failover_config(default_url: &str) -> serde_json::Value {
+        json!({
+            "backends": [{
+                "areas": [{
+                    "exterior": [{"x": 0, "y": 0}, {"x": 10, "y": 0}, {"x": 10, "y": 10}, {"x": 0, "y": 10}, {"x": 0, "y": 0}],
+                    "interiors": [],
+                }],
+                "backend": {"name": "unreachable", "base_url": "http://127.0.0.1:1"},
+            }],
+            "default_backend": {"base_url": default_url},
+            "retries": 1,
+        })
+    }
+
+    fn send(proxy: &Proxy, method: Method) -> Response<Body> {
+        let req = Request::builder()
+            .method(method)
+            .uri("http://proxy/resource")
+            .header("Geolocation", "[5, 5]")
+            .body(Body::empty())
+            .unwrap();
+
+        Runtime::new().unwrap().block_on(proxy.handle(req)).unwrap()
+    }
+
+    #[test]
+    fn geoip_location() {
+        let (london_url, london) = upstream();
+        let (default_url, default) = upstream();
+        let dir = temp_dir();
+        let geoip = Arc::new(
+            GeoIp::open(
+                write_geoip_db(&dir, &[("81.2.69.142/31", (-0.0931, 51.5142))]),
+                Vec::new(),
+            )
+            .unwrap(),
+        );
+        let (metrics, sink) = metrics();
+        let proxy = |addr: &str| {
+            Proxy::new(
+                state(json!({
+                    "backends": [{
+                        "areas": [{
+                            "exterior": [{"x": -1, "y": 51}, {"x": 1, "y": 51}, {"x": 1, "y": 52}, {"x": -1, "y": 52}, {"x": -1, "y": 51}],
+                            "interiors": [],
+                        }],
+                        "backend": {"name": "london", "base_url": london_url},
+                    }],
+                    "default_backend": {"base_url": default_url},
+                })),
+                metrics.clone(),
+                Arc::new(AccessLog::new(LogFormat::Text)),
+                Some(geoip.clone()),
+                Peer {
+                    addr: addr.parse().unwrap(),
+                    ..peer()
+                },
+            )
+        };
+        let get = |proxy: &Proxy| {
+            let req = Request::get("http://proxy/resource")
+                .body(Body::empty())
+                .unwrap();
+
+            Runtime::new().unwrap().block_on(proxy.handle(req)).unwrap()
+        };
+
+        assert_eq!(get(&proxy("81.2.69.142")).status(), StatusCode::OK);
+        assert_eq!(london.load(Ordering::SeqCst), 1);
+        assert!(sink.contains("location.geoip"));
+
+        // an address missing from the database falls through to the default backend
+        assert_eq!(get(&proxy("192.0.2.1")).status(), StatusCode::OK);
+        assert_eq!(london.load(Ordering::SeqCst), 1);
+        assert_eq!(default.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn response_timeout() {
+        let proxy = proxy(json!({
+            "default_backend": {
+                "base_url": hung_upstream(),
+                "response_timeout_ms": 100,
+            },
+        }));
+
+        assert_eq!(
+            send(&proxy, Method::GET).status(),
+            StatusCode::GATEWAY_TIMEOUT
+        );
+    }
+
+    #[test]
+    fn connect_timeout() {
+        let black_hole = black_hole();
+        let (metrics, sink) = metrics();
+        let proxy = Proxy::new(
+            state(json!({
+                "default_backend": {
+                    "base_url": black_hole.url,
+                    "connect_timeout_ms": 100,
+                },
+            })),
+            metrics,
+            Arc::new(AccessLog::new(LogFormat::Text)),
+            None,
+            peer(),
+        );
+
+        assert_eq!(
+            send(&proxy, Method::GET).status(),
+            StatusCode::GATEWAY_TIMEOUT
+        );
+        assert!(sink.contains("requests.timeout"));
+        assert!(!sink.contains("requests.failed"));
+    }
+
+    #[test]
+    fn access_log_bytes() {
+        let dir = temp_dir();
+        let path = dir.path().join("access.log");
+        let file = LogFile::open(&path, RotationPolicy::default()).unwrap();
+        let access_log = Arc::new(AccessLog::to_file(
+            LogWriter::spawn(file, "access-log-test").unwrap(),
+            "{status} {bytes}".parse().unwrap(),
+        ));
+        let proxy = Proxy::new(
+            state(json!({
+                "default_backend": {"base_url": chunked_upstream()},
+            })),
+            setup_metrics(None::<&str>, TagFormat::Dotted).unwrap(),
+            access_log.clone(),
+            None,
+            peer(),
+        );
+
+        let req = Request::get("http://proxy/resource")
+            .body(Body::empty())
+            .unwrap();
+        let body = Runtime::new()
+            .unwrap()
+            .block_on(
+                proxy
+                    .handle(req)
+                    .and_then(|resp| resp.into_body().concat2()),
+            )
+            .unwrap();
+        assert_eq!(&body[..], b"hello world");
+
+        // the size of chunked bodies is only known once they are sent
+        access_log.flush();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "200 11\n");
+    }
+
+    #[test]
+    fn retry_next_backend() {
+        let (url, requests) = upstream();
+        let proxy = proxy(failover_config(&url));
+
+        assert_eq!(send(&proxy, Method::GET).status(), StatusCode::OK);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn no_retry_non_idempotent() {
+        let (url, requests) = upstream();
+        let proxy = proxy(failover_config(&url));
+
+        assert_eq!(send(&proxy, Method::POST).status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn method_not_allowed() {
+        let proxy = proxy(json!({
+            "default_backend": {
+                "base_url": "http://127.0.0.1:1",
+                "methods": ["GET", "HEAD"],
+            },
+        }));
+        let req = Request::post("http://proxy/upload")
+            .body(Body::empty())
+            .unwrap();
+
+        let resp = Runtime::new().unwrap().block_on(proxy.handle(req)).unwrap();
+
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(resp.headers()[ALLOW], "GET, HEAD");
+    }
+}
@@ -0,0 +1,283 @@
+use crate::error::*;
+use log::*;
+
+use futures::{
+    future::{self, Future},
+    stream::Stream,
+    sync::mpsc,
+};
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc::channel, Arc};
+use std::thread;
+use std::time::Duration;
+use tokio_signal::unix::{Signal, SIGHUP};
+use tokio_threadpool::blocking;
+
+use crate::config::read_config;
+use crate::health::{carry_over, HealthChecker};
+use crate::index::Precision;
+use crate::metrics::*;
+use crate::state::{ProxyState, SharedState};
+
+// delay used to coalesce bursts of filesystem events (e.g. editor save sequences)
+const WATCH_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Watch files for changes, forwarding them as a stream of events
+///
+/// Parent directories are watched, as editors and config management tools
+/// tend to replace files instead of writing to them.
+pub(crate) fn watch_files(
+    paths: &[PathBuf],
+    thread_name: &str,
+) -> Result<impl Stream<Item = &'static str, Error = ()>> {
+    let paths = paths
+        .iter()
+        .map(|path| path.canonicalize())
+        .collect::<std::io::Result<HashSet<_>>>()?;
+    let dirs = paths
+        .iter()
+        .map(|path| {
+            path.parent()
+                .map(Path::to_owned)
+                .unwrap_or_else(|| PathBuf::from("/"))
+        })
+        .collect::<HashSet<_>>();
+
+    let (tx, rx) = mpsc::unbounded();
+    let (watch_tx, watch_rx) = channel();
+
+    let mut watcher = watcher(watch_tx, WATCH_DEBOUNCE)?;
+    dirs.iter()
+        .try_for_each(|dir| watcher.watch(dir, RecursiveMode::NonRecursive))?;
+
+    thread::Builder::new()
+        .name(thread_name.to_owned())
+        .spawn(move || {
+            // keep the watcher alive as long as the thread runs
+            let _watcher = watcher;
+
+            for event in watch_rx {
+                let changed = match event {
+                    DebouncedEvent::Create(ref changed)
+                    | DebouncedEvent::Write(ref changed)
+                    | DebouncedEvent::Rename(_, ref changed) => paths.contains(changed),
+                    DebouncedEvent::Error(error, _) => {
+                        error!("File watcher error: {}", error);
+                        false
+                    }
+                    _ => false,
+                };
+
+                if changed && tx.unbounded_send("file change").is_err() {
+                    break;
+                }
+            }
+        })?;
+
+    Ok(rx)
+}
+
+/// Stream ending at the first error of the inner stream, the error is logged
+fn until_error<S>(stream: S, what: &'static str) -> impl Stream<Item = S::Item, Error = ()>
+where
+    S: Stream,
+    S::Error: Display,
+{
+    stream
+        .map(Some)
+        .or_else(move |error| {
+            error!("Unable to handle {}: {}", what, error);
+
+            Ok(None)
+        })
+        .take_while(|item| Ok(item.is_some()))
+        .filter_map(|item| item)
+}
+
+/// SIGHUP signals, failing to handle them does not end reloads on file changes
+pub(crate) fn sighups() -> impl Stream<Item = &'static str, Error = ()> {
+    until_error(
+        Signal::new(SIGHUP).flatten_stream().map(|_| "SIGHUP"),
+        "SIGHUP",
+    )
+}
+
+pub(crate) struct ConfigReloader {
+    path: PathBuf,
+    // coordinate precision given on the command line
+    precision: Option<Precision>,
+    state: SharedState,
+    checker: HealthChecker,
+    metrics: MetricsClient,
+}
+
+impl ConfigReloader {
+    pub(crate) fn new(
+        path: impl AsRef<Path>,
+        precision: Option<Precision>,
+        state: SharedState,
+        checker: HealthChecker,
+        metrics: MetricsClient,
+    ) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            precision,
+            state,
+            checker,
+            metrics,
+        }
+    }
+
+    /// Rebuild the index from the config file and swap it in,
+    /// the previous index stays active if the new config is invalid
+    fn reload(&self, reason: &str) {
+        info!("Reloading config {} ({})", self.path.display(), reason);
+
+        match read_config(&self.path, self.precision) {
+            Ok(config) => {
+                let state = Arc::new(ProxyState::from(config));
+                carry_over(&self.state.load().index, &state.index);
+                self.checker.spawn(&state.index);
+                self.state.store(state);
+
+                info!("Config {} reloaded", self.path.display());
+                self.metrics.incr("config.reloaded");
+            }
+            Err(error) => {
+                error!(
+                    "Unable to reload config {}, keeping the previous one: {}",
+                    self.path.display(),
+                    error
+                );
+                self.metrics.incr("config.reload_failed");
+            }
+        }
+    }
+
+    /// Future reloading the config on SIGHUP and whenever the config file changes,
+    /// reloads run as blocking sections so they don't hold up requests on the runtime
+    pub(crate) fn watch(self) -> Result<impl Future<Item = (), Error = ()>> {
+        let file_changes = watch_files(std::slice::from_ref(&self.path), "config-watcher")?;
+
+        let reloader = Arc::new(self);
+
+        Ok(sighups().select(file_changes).for_each(move |reason| {
+            let reloader = reloader.clone();
+
+            future::poll_fn(move || blocking(|| reloader.reload(reason))).or_else(|error| {
+                error!("Unable to reload config: {}", error);
+
+                Ok(())
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arc_swap::ArcSwap;
+    use futures::stream;
+    use serde_json::json;
+    use tokio::runtime::Runtime;
+
+    use crate::testing::{temp_dir, write_config};
+
+    fn reload(runtime: &mut Runtime, reloader: &Arc<ConfigReloader>) {
+        let reloader = reloader.clone();
+
+        runtime
+            .block_on(future::lazy(move || {
+                reloader.reload("test");
+
+                Ok::<_, ()>(())
+            }))
+            .unwrap();
+    }
+
+    fn default_url(state: &SharedState) -> String {
+        state.load().index.default().upstreams()[0]
+            .url()
+            .to_string()
+    }
+
+    #[test]
+    fn reload_config() {
+        let dir = temp_dir();
+
+        let config = |url: &str, interval_ms: u64| {
+            json!({
+                "backends": [],
+                "default_backend": {
+                    "base_url": url,
+                    "health_check": {"interval_ms": interval_ms, "unhealthy_threshold": 3},
+                },
+            })
+        };
+
+        let path = write_config(&dir, &config("http://127.0.0.1:3", 10));
+        let state: SharedState = Arc::new(ArcSwap::from_pointee(ProxyState::from(
+            read_config(&path, None).unwrap(),
+        )));
+        let metrics = setup_metrics(None::<&str>, TagFormat::Dotted).unwrap();
+        let reloader = Arc::new(ConfigReloader::new(
+            &path,
+            None,
+            state.clone(),
+            HealthChecker::new(metrics.clone()),
+            metrics,
+        ));
+        let mut runtime = Runtime::new().unwrap();
+
+        // invalid configs leave the previous state in place
+        write_config(&dir, &json!({"backends": []}));
+        reload(&mut runtime, &reloader);
+        assert_eq!(default_url(&state), "http://127.0.0.1:3/");
+
+        write_config(&dir, &config("http://127.0.0.1:1", 10));
+        reload(&mut runtime, &reloader);
+        assert_eq!(default_url(&state), "http://127.0.0.1:1/");
+
+        // health checks of the new backends are running, the upstream is unreachable
+        let checked = (0..100).any(|_| {
+            thread::sleep(Duration::from_millis(20));
+            !state.load().index.default().is_healthy()
+        });
+        assert!(checked);
+
+        // the upstream stays unhealthy, though the next check is far off
+        write_config(&dir, &config("http://127.0.0.1:1", 60_000));
+        reload(&mut runtime, &reloader);
+        assert!(!state.load().index.default().is_healthy());
+
+        // other upstreams start out healthy
+        write_config(&dir, &config("http://127.0.0.1:2", 60_000));
+        reload(&mut runtime, &reloader);
+        assert_eq!(default_url(&state), "http://127.0.0.1:2/");
+        assert!(state.load().index.default().is_healthy());
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn signal_failure() {
+        let signals = stream::iter_result(vec![Ok("SIGHUP"), Err("no signal handler")]);
+        let file_changes = stream::iter_ok(vec!["file change"; 3]);
+
+        let mut reasons = until_error(signals, "SIGHUP")
+            .select(file_changes)
+            .collect()
+            .wait()
+            .unwrap();
+        reasons.sort();
+
+        // file changes are still forwarded once signals fail
+        assert_eq!(
+            reasons,
+            vec!["SIGHUP", "file change", "file change", "file change"]
+        );
+    }
+}
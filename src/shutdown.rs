@@ -0,0 +1,113 @@
+use log::*;
+
+use futures::future::{self, Either, Future};
+use futures::stream::Stream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+use tokio_signal::unix::{Signal, SIGINT, SIGTERM};
+
+/// Shutdown of the proxy on SIGTERM or SIGINT
+#[derive(Debug, Default)]
+pub(crate) struct Shutdown {
+    draining: AtomicBool,
+}
+
+impl Shutdown {
+    /// Whether connections are being drained, the proxy is no longer ready to take requests
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Future resolved on the first SIGTERM or SIGINT,
+    /// it never resolves if the signals can't be handled
+    pub(crate) fn signal(self: Arc<Self>) -> impl Future<Item = (), Error = ()> {
+        let signals = Signal::new(SIGTERM)
+            .flatten_stream()
+            .map(|_| "SIGTERM")
+            .select(Signal::new(SIGINT).flatten_stream().map(|_| "SIGINT"));
+
+        signals.into_future().then(move |result| match result {
+            Ok((Some(signal), _)) => {
+                info!("Received {}, draining connections", signal);
+                self.draining.store(true, Ordering::Relaxed);
+
+                Either::A(future::ok(()))
+            }
+            Ok((None, _)) => Either::B(future::empty()),
+            Err((error, _)) => {
+                error!("Unable to handle SIGTERM and SIGINT: {}", error);
+
+                Either::B(future::empty())
+            }
+        })
+    }
+}
+
+/// How the server stopped running
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Stopped {
+    /// Every connection was closed after the shutdown signal
+    Drained,
+    /// Connections were still open when the drain timeout passed
+    TimedOut,
+    /// The server failed, with or without the shutdown signal
+    Failed,
+}
+
+/// Run the server until it's drained after the shutdown signal, or the drain timeout passes
+///
+/// The server has to stop accepting connections and finish the requests in flight on its own
+/// once the signal resolves.
+pub(crate) fn drain(
+    server: impl Future<Item = (), Error = ()>,
+    signal: impl Future<Item = (), Error = ()>,
+    timeout: Duration,
+) -> impl Future<Item = Stopped, Error = ()> {
+    let deadline = signal.and_then(move |_| {
+        Delay::new(Instant::now() + timeout).map_err(|error| error!("Drain timer error: {}", error))
+    });
+
+    server.select2(deadline).then(|result| match result {
+        Ok(Either::A(_)) => Ok(Stopped::Drained),
+        Err(Either::A(_)) => Ok(Stopped::Failed),
+        Ok(Either::B(_)) | Err(Either::B(_)) => Ok(Stopped::TimedOut),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::Runtime;
+
+    fn run(
+        server: impl Future<Item = (), Error = ()> + Send + 'static,
+        signal: impl Future<Item = (), Error = ()> + Send + 'static,
+    ) -> Stopped {
+        Runtime::new()
+            .unwrap()
+            .block_on(drain(server, signal, Duration::from_millis(100)))
+            .unwrap()
+    }
+
+    fn after(millis: u64) -> impl Future<Item = (), Error = ()> {
+        Delay::new(Instant::now() + Duration::from_millis(millis)).map_err(|_| ())
+    }
+
+    #[test]
+    fn drained() {
+        assert_eq!(run(after(20), future::ok(())), Stopped::Drained);
+    }
+
+    #[test]
+    fn drain_timeout() {
+        // the server keeps connections open long after the signal
+        assert_eq!(run(after(5000), after(10)), Stopped::TimedOut);
+    }
+
+    #[test]
+    fn failed() {
+        assert_eq!(run(future::err(()), future::empty()), Stopped::Failed);
+    }
+}
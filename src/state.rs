@@ -0,0 +1,60 @@
+use arc_swap::ArcSwap;
+use std::sync::Arc;
+
+use crate::config::{Backend, ProxyConfig};
+use crate::headers::HeadersConfig;
+use crate::index::AreaIndex;
+use crate::location::{AxisOrder, LocationSource};
+use crate::util::setup_index;
+
+/// Routing state built from the config, replaced as a whole on config reload
+#[derive(Debug)]
+pub(crate) struct ProxyState {
+    /// Config the state was built from, as served by the admin endpoint
+    pub(crate) config: serde_json::Value,
+    pub(crate) index: AreaIndex,
+    pub(crate) location: Vec<LocationSource>,
+    pub(crate) axis_order: AxisOrder,
+    pub(crate) headers: HeadersConfig,
+    pub(crate) retries: usize,
+}
+
+/// State shared between the proxy service and the config reloader
+pub(crate) type SharedState = Arc<ArcSwap<ProxyState>>;
+
+impl From<ProxyConfig> for ProxyState {
+    fn from(mut config: ProxyConfig) -> Self {
+        let dump = config.dump();
+        let location = std::mem::take(&mut config.location);
+        let headers = std::mem::take(&mut config.headers);
+        let retries = config.retries;
+        let axis_order = config.axis_order;
+
+        Self {
+            config: dump,
+            index: setup_index(config),
+            location,
+            axis_order,
+            headers,
+            retries,
+        }
+    }
+}
+
+impl ProxyState {
+    /// Name of the region reported to the client
+    pub(crate) fn region(&self, backend: &Backend) -> Option<String> {
+        backend.name().map(str::to_owned).or_else(|| {
+            if std::ptr::eq(backend, self.index.default()) {
+                Some("default".to_owned())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Backend label of request metrics, its upstreams if the backend has no name
+    pub(crate) fn backend_label(&self, backend: &Backend) -> String {
+        self.region(backend).unwrap_or_else(|| backend.to_string())
+    }
+}
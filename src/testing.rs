@@ -0,0 +1,252 @@
+//! Fixtures shared by the tests of several modules
+
+use cadence::{MetricSink, StatsdClient};
+use ipnetwork::Ipv4Network;
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::x509::{extension::SubjectAlternativeName, X509Name, X509};
+use serde_json::{json, Value};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+
+use crate::metrics::{Metrics, MetricsClient, TagFormat};
+use crate::tls::CertificateFiles;
+
+/// Directory unique to the test, removed once dropped
+pub(crate) fn temp_dir() -> TempDir {
+    tempfile::Builder::new()
+        .prefix("geoproxy-")
+        .tempdir()
+        .unwrap()
+}
+
+/// Write the config to `config.json` in the directory
+pub(crate) fn write_config(dir: &TempDir, config: &Value) -> PathBuf {
+    let path = dir.path().join("config.json");
+    fs::write(&path, config.to_string()).unwrap();
+
+    path
+}
+
+/// Statsd metrics sent by a client
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SpySink(Arc<Mutex<Vec<String>>>);
+
+impl SpySink {
+    /// Whether a metric with the name was sent, tags appended to the name included
+    pub(crate) fn contains(&self, metric: &str) -> bool {
+        let prefix = format!("geoproxy.{}", metric);
+
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|sent| sent.starts_with(&prefix))
+    }
+}
+
+impl MetricSink for SpySink {
+    fn emit(&self, metric: &str) -> io::Result<usize> {
+        self.0.lock().unwrap().push(metric.to_owned());
+
+        Ok(metric.len())
+    }
+}
+
+/// Metrics client recording the statsd metrics it sends
+pub(crate) fn metrics() -> (MetricsClient, SpySink) {
+    let sink = SpySink::default();
+    let statsd = StatsdClient::from_sink("geoproxy", sink.clone());
+
+    (
+        Arc::new(Metrics::new(statsd, TagFormat::Dotted).unwrap()),
+        sink,
+    )
+}
+
+/// Self-signed certificate valid for the DNS name, with a random serial number
+pub(crate) fn certificate(name: &str) -> (X509, PKey<Private>) {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+    let mut subject = X509Name::builder().unwrap();
+    subject.append_entry_by_text("CN", name).unwrap();
+    let subject = subject.build();
+
+    let mut serial = BigNum::new().unwrap();
+    serial.rand(64, MsbOption::MAYBE_ZERO, false).unwrap();
+
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    builder
+        .set_serial_number(&serial.to_asn1_integer().unwrap())
+        .unwrap();
+    builder.set_subject_name(&subject).unwrap();
+    builder.set_issuer_name(&subject).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    let san = SubjectAlternativeName::new()
+        .dns(name)
+        .build(&builder.x509v3_context(None, None))
+        .unwrap();
+    builder.append_extension(san).unwrap();
+    builder.sign(&key, MessageDigest::sha256()).unwrap();
+
+    (builder.build(), key)
+}
+
+/// Write a new certificate for the DNS name to `<name>.pem` and `<name>.key` in the directory
+pub(crate) fn write_certificate(dir: &TempDir, name: &str) -> (CertificateFiles, X509) {
+    let (cert, key) = certificate(name);
+    let files = CertificateFiles {
+        cert: dir.path().join(format!("{}.pem", name)),
+        key: dir.path().join(format!("{}.key", name)),
+    };
+
+    fs::write(&files.cert, cert.to_pem().unwrap()).unwrap();
+    fs::write(&files.key, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+    (files, cert)
+}
+
+/// Append a MaxMind DB data field with the type and payload size
+fn mmdb_control(out: &mut Vec<u8>, kind: u8, size: usize) {
+    assert!(
+        size < 29,
+        "MaxMind DB field too large for the test database"
+    );
+
+    // types above 7 are extended, stored in the byte after the control byte
+    if kind <= 7 {
+        out.push(kind << 5 | size as u8);
+    } else {
+        out.push(size as u8);
+        out.push(kind - 7);
+    }
+}
+
+/// Append a JSON value in the MaxMind DB data format
+fn mmdb_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::String(string) => {
+            mmdb_control(out, 2, string.len());
+            out.extend(string.as_bytes());
+        }
+        Value::Number(number) => match number.as_u64() {
+            Some(number) => {
+                mmdb_control(out, 6, 4);
+                out.extend(&(number as u32).to_be_bytes());
+            }
+            None => {
+                mmdb_control(out, 3, 8);
+                out.extend(&number.as_f64().unwrap().to_be_bytes());
+            }
+        },
+        Value::Object(map) => {
+            mmdb_control(out, 7, map.len());
+
+            for (key, value) in map {
+                mmdb_value(out, &Value::String(key.clone()));
+                mmdb_value(out, value);
+            }
+        }
+        Value::Array(values) => {
+            mmdb_control(out, 11, values.len());
+            values.iter().for_each(|value| mmdb_value(out, value));
+        }
+        _ => panic!("{} has no MaxMind DB type in the test database", value),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum MmdbRecord {
+    Empty,
+    Node(usize),
+    Data(usize),
+}
+
+/// Write an IPv4 MaxMind city database locating the networks at the `(longitude, latitude)`
+/// to `geoip.mmdb` in the directory
+pub(crate) fn write_geoip_db(dir: &TempDir, cities: &[(&str, (f64, f64))]) -> PathBuf {
+    let mut nodes = vec![[MmdbRecord::Empty; 2]];
+    let mut data = Vec::new();
+
+    for (network, (longitude, latitude)) in cities {
+        let network = network.parse::<Ipv4Network>().unwrap();
+        let bits = u32::from(network.network());
+        let record = MmdbRecord::Data(data.len());
+        mmdb_value(
+            &mut data,
+            &json!({"location": {"latitude": latitude, "longitude": longitude}}),
+        );
+
+        let mut node = 0;
+
+        for depth in 0..network.prefix() {
+            let bit = (bits >> (31 - depth) & 1) as usize;
+
+            if depth + 1 == network.prefix() {
+                nodes[node][bit] = record;
+                break;
+            }
+
+            node = match nodes[node][bit] {
+                MmdbRecord::Node(next) => next,
+                MmdbRecord::Empty => {
+                    nodes.push([MmdbRecord::Empty; 2]);
+                    nodes[node][bit] = MmdbRecord::Node(nodes.len() - 1);
+                    nodes.len() - 1
+                }
+                MmdbRecord::Data(_) => panic!("{} is within another network", network),
+            };
+        }
+    }
+
+    // 24 bit records, empty ones point at the node count, data ones past the separator
+    let node_count = nodes.len();
+    let mut db = Vec::new();
+
+    for record in nodes.iter().flatten() {
+        let value = match *record {
+            MmdbRecord::Empty => node_count,
+            MmdbRecord::Node(node) => node,
+            MmdbRecord::Data(offset) => node_count + 16 + offset,
+        };
+        db.extend(&(value as u32).to_be_bytes()[1..]);
+    }
+
+    db.extend(&[0; 16]);
+    db.extend(data);
+    db.extend(b"\xab\xcd\xefMaxMind.com");
+    mmdb_value(
+        &mut db,
+        &json!({
+            "binary_format_major_version": 2,
+            "binary_format_minor_version": 0,
+            "build_epoch": 0,
+            "database_type": "GeoLite2-City",
+            "description": {"en": "geoproxy test database"},
+            "ip_version": 4,
+            "languages": ["en"],
+            "node_count": node_count,
+            "record_size": 24,
+        }),
+    );
+
+    let path = dir.path().join("geoip.mmdb");
+    fs::write(&path, db).unwrap();
+
+    path
+}
@@ -0,0 +1,473 @@
+use crate::error::*;
+use log::*;
+
+use arc_swap::ArcSwap;
+use failure::format_err;
+use futures::{
+    future::{self, Future, MapErr},
+    stream::{FuturesUnordered, Stream},
+    Async, Poll,
+};
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{HandshakeError, NameType, SniError, SslAcceptor, SslContext, SslMethod};
+use openssl::x509::{X509Ref, X509};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::timer::Timeout;
+use tokio_openssl::{AcceptAsync, SslAcceptorExt, SslStream};
+
+use crate::metrics::*;
+use crate::reload::{sighups, watch_files};
+
+// connections not done with the handshake in time are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Certificate and private key files, both PEM encoded
+///
+/// The certificate file may hold the whole chain, leaf certificate first.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct CertificateFiles {
+    pub(crate) cert: PathBuf,
+    pub(crate) key: PathBuf,
+}
+
+/// TLS settings of the listening socket
+///
+/// The first certificate is used for clients not sending SNI
+/// or asking for a name none of the certificates is valid for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct TlsConfig {
+    pub(crate) certificates: Vec<CertificateFiles>,
+}
+
+impl TlsConfig {
+    pub(crate) fn single(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        Self {
+            certificates: vec![CertificateFiles {
+                cert: cert.into(),
+                key: key.into(),
+            }],
+        }
+    }
+
+    /// Resolve relative paths against the directory of the config file
+    pub(crate) fn resolve_paths(&mut self, base_dir: &Path) {
+        self.certificates.iter_mut().for_each(|files| {
+            files.cert = base_dir.join(&files.cert);
+            files.key = base_dir.join(&files.key);
+        });
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.certificates.is_empty() {
+            Err(format_err!(
+                "At least one TLS certificate has to be declared"
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn paths(&self) -> Vec<PathBuf> {
+        self.certificates
+            .iter()
+            .flat_map(|files| vec![files.cert.clone(), files.key.clone()])
+            .collect()
+    }
+}
+
+pub(crate) fn read_file(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|error| format_err!("Unable to read {}: {}", path.display(), error))
+}
+
+pub(crate) struct Certificate {
+    pub(crate) chain: Vec<X509>,
+    pub(crate) key: PKey<Private>,
+}
+
+impl Certificate {
+    pub(crate) fn load(files: &CertificateFiles) -> Result<Self> {
+        let chain = X509::stack_from_pem(&read_file(&files.cert)?)?;
+        let key = PKey::private_key_from_pem(&read_file(&files.key)?)?;
+
+        if chain.is_empty() {
+            return Err(format_err!(
+                "No certificate found in {}",
+                files.cert.display()
+            ));
+        }
+
+        Ok(Self { chain, key })
+    }
+
+    /// DNS names the certificate is valid for, lowercase
+    fn server_names(&self) -> Vec<String> {
+        let leaf = &self.chain[0];
+
+        let mut names = leaf
+            .subject_alt_names()
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| name.dnsname().map(str::to_lowercase))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        if names.is_empty() {
+            names.extend(common_names(leaf));
+        }
+
+        names
+    }
+
+    fn acceptor(
+        &self,
+        server_names: Option<Arc<HashMap<String, SslContext>>>,
+    ) -> Result<SslAcceptor> {
+        let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
+
+        builder.set_certificate(&self.chain[0])?;
+        self.chain[1..]
+            .iter()
+            .try_for_each(|cert| builder.add_extra_chain_cert(cert.clone()))?;
+        builder.set_private_key(&self.key)?;
+        builder.check_private_key()?;
+
+        if let Some(server_names) = server_names {
+            builder.set_servername_callback(move |ssl, _alert| {
+                let context = ssl
+                    .servername(NameType::HOST_NAME)
+                    .and_then(|name| find_by_name(&server_names, name));
+
+                if let Some(context) = context {
+                    ssl.set_ssl_context(context)
+                        .map_err(|_| SniError::ALERT_FATAL)?;
+                }
+
+                Ok(())
+            });
+        }
+
+        Ok(builder.build())
+    }
+}
+
+fn common_names(cert: &X509Ref) -> Vec<String> {
+    cert.subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .filter_map(|entry| entry.data().to_string().ok())
+        .map(|name| name.to_lowercase())
+        .collect()
+}
+
+/// Entry for the server name, exact names take precedence over wildcards
+fn find_by_name<'a, V>(entries: &'a HashMap<String, V>, name: &str) -> Option<&'a V> {
+    let name = name.to_lowercase();
+
+    entries.get(&name).or_else(|| {
+        let (_, parent) = name.split_at(name.find('.')?);
+
+        entries.get(&format!("*{}", parent))
+    })
+}
+
+/// Build the acceptor handshakes start with, switching to the certificate
+/// matching the requested server name
+fn build_acceptor(config: &TlsConfig) -> Result<SslAcceptor> {
+    let certificates = config
+        .certificates
+        .iter()
+        .map(Certificate::load)
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut server_names = HashMap::new();
+
+    for certificate in &certificates {
+        let context = certificate.acceptor(None)?.into_context();
+
+        for name in certificate.server_names() {
+            // the first certificate declared for a name wins
+            server_names.entry(name).or_insert_with(|| context.clone());
+        }
+    }
+
+    certificates[0].acceptor(Some(Arc::new(server_names)))
+}
+
+/// TLS acceptor of the listening socket, with certificates reloadable at runtime
+pub(crate) struct TlsAcceptor {
+    config: TlsConfig,
+    acceptor: ArcSwap<SslAcceptor>,
+    metrics: MetricsClient,
+}
+
+impl TlsAcceptor {
+    pub(crate) fn new(config: TlsConfig, metrics: MetricsClient) -> Result<Self> {
+        let acceptor = build_acceptor(&config)?;
+
+        Ok(Self {
+            config,
+            acceptor: ArcSwap::from_pointee(acceptor),
+            metrics,
+        })
+    }
+
+    /// Reload the certificates, the previous ones stay in use if any of them is invalid
+    fn reload(&self, reason: &str) {
+        info!("Reloading TLS certificates ({})", reason);
+
+        match build_acceptor(&self.config) {
+            Ok(acceptor) => {
+                self.acceptor.store(Arc::new(acceptor));
+
+                info!("TLS certificates reloaded");
+                self.metrics.incr("tls.reloaded");
+            }
+            Err(error) => {
+                error!(
+                    "Unable to reload TLS certificates, keeping the previous ones: {}",
+                    error
+                );
+                self.metrics.incr("tls.reload_failed");
+            }
+        }
+    }
+
+    /// Future reloading the certificates on SIGHUP and whenever their files change
+    pub(crate) fn watch(self: Arc<Self>) -> Result<impl Future<Item = (), Error = ()>> {
+        let file_changes = watch_files(&self.config.paths(), "tls-watcher")?;
+
+        Ok(sighups().select(file_changes).for_each(move |reason| {
+            self.reload(reason);
+
+            future::ok(())
+        }))
+    }
+
+    fn accept(&self, stream: AddrStream) -> Handshake {
+        self.acceptor
+            .load()
+            .accept_async(stream)
+            .map_err(handshake_error)
+    }
+}
+
+/// Stream with an established TLS session
+pub(crate) type TlsStream<S> = SslStream<S>;
+
+type Handshake = MapErr<AcceptAsync<AddrStream>, fn(HandshakeError<AddrStream>) -> io::Error>;
+
+pub(crate) fn handshake_error<S: fmt::Debug>(error: HandshakeError<S>) -> io::Error {
+    io::Error::other(error.to_string())
+}
+
+/// Accepted connections with completed TLS handshakes
+///
+/// Handshakes run concurrently, a slow client does not hold up the others.
+pub(crate) struct TlsIncoming {
+    incoming: AddrIncoming,
+    acceptor: Arc<TlsAcceptor>,
+    handshakes: FuturesUnordered<Timeout<Handshake>>,
+}
+
+impl TlsIncoming {
+    pub(crate) fn new(incoming: AddrIncoming, acceptor: Arc<TlsAcceptor>) -> Self {
+        Self {
+            incoming,
+            acceptor,
+            handshakes: FuturesUnordered::new(),
+        }
+    }
+}
+
+impl Stream for TlsIncoming {
+    type Item = TlsStream<AddrStream>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        while let Async::Ready(stream) = self.incoming.poll()? {
+            let stream = match stream {
+                Some(stream) => stream,
+                None => return Ok(Async::Ready(None)),
+            };
+
+            self.handshakes.push(Timeout::new(
+                self.acceptor.accept(stream),
+                HANDSHAKE_TIMEOUT,
+            ));
+        }
+
+        loop {
+            match self.handshakes.poll() {
+                Ok(Async::Ready(Some(stream))) => return Ok(Async::Ready(Some(stream))),
+                // the listener is still pending, so the task gets notified
+                // about new connections even with no handshakes in progress
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(error) => {
+                    debug!("TLS handshake failed: {}", error);
+                    self.acceptor.metrics.incr("tls.handshake_failed");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::ssl::{SslConnector, SslVerifyMode};
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::thread;
+    use tokio::runtime::Runtime;
+
+    use crate::testing::{metrics, temp_dir, write_certificate, SpySink};
+
+    /// Listener accepting TLS connections on a local port, dropping them after the handshake
+    fn listen(acceptor: Arc<TlsAcceptor>, runtime: &mut Runtime) -> SocketAddr {
+        let incoming = AddrIncoming::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = incoming.local_addr();
+
+        runtime.spawn(
+            TlsIncoming::new(incoming, acceptor)
+                .for_each(|_| Ok(()))
+                .map_err(|error| panic!("TLS listener failed: {}", error)),
+        );
+
+        addr
+    }
+
+    /// Certificate served for the server name, sent with SNI if any
+    fn served(addr: SocketAddr, server_name: Option<&str>) -> X509 {
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        let config = connector
+            .build()
+            .configure()
+            .unwrap()
+            .use_server_name_indication(server_name.is_some())
+            .verify_hostname(false);
+
+        let stream = config
+            .connect(
+                server_name.unwrap_or("unknown"),
+                TcpStream::connect(addr).unwrap(),
+            )
+            .unwrap();
+
+        stream.ssl().peer_certificate().unwrap()
+    }
+
+    fn assert_served(addr: SocketAddr, server_name: Option<&str>, cert: &X509) {
+        assert_eq!(
+            served(addr, server_name).to_der().unwrap(),
+            cert.to_der().unwrap(),
+            "certificate served for {:?}",
+            server_name
+        );
+    }
+
+    /// Wait for the metric to be sent, the listener runs on another thread
+    fn sent(sink: &SpySink, metric: &str) -> bool {
+        (0..100).any(|_| {
+            thread::sleep(Duration::from_millis(10));
+            sink.contains(metric)
+        })
+    }
+
+    #[test]
+    fn server_names() {
+        let entries = vec![
+            ("example.com", 1),
+            ("*.example.com", 2),
+            ("api.example.com", 3),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_owned(), value))
+        .collect::<HashMap<_, _>>();
+
+        assert_eq!(find_by_name(&entries, "example.com"), Some(&1));
+        assert_eq!(find_by_name(&entries, "API.example.com"), Some(&3));
+        assert_eq!(find_by_name(&entries, "www.example.com"), Some(&2));
+        assert_eq!(find_by_name(&entries, "a.b.example.com"), None);
+        assert_eq!(find_by_name(&entries, "example.org"), None);
+    }
+
+    #[test]
+    fn certificate_by_server_name() {
+        let dir = temp_dir();
+        let (first_files, first) = write_certificate(&dir, "first.test");
+        let (second_files, second) = write_certificate(&dir, "second.test");
+        let config = TlsConfig {
+            certificates: vec![first_files, second_files],
+        };
+        let mut runtime = Runtime::new().unwrap();
+        let addr = listen(
+            Arc::new(TlsAcceptor::new(config, metrics().0).unwrap()),
+            &mut runtime,
+        );
+
+        assert_served(addr, Some("first.test"), &first);
+        assert_served(addr, Some("SECOND.test"), &second);
+        // the first certificate is the default one
+        assert_served(addr, Some("other.test"), &first);
+        assert_served(addr, None, &first);
+    }
+
+    #[test]
+    fn reload_certificates() {
+        let dir = temp_dir();
+        let (files, first) = write_certificate(&dir, "proxy.test");
+        let (metrics, sink) = metrics();
+        let acceptor =
+            Arc::new(TlsAcceptor::new(TlsConfig::single(files.cert, files.key), metrics).unwrap());
+        let mut runtime = Runtime::new().unwrap();
+        let addr = listen(acceptor.clone(), &mut runtime);
+
+        assert_served(addr, Some("proxy.test"), &first);
+
+        let (files, second) = write_certificate(&dir, "proxy.test");
+        acceptor.reload("test");
+        assert_served(addr, Some("proxy.test"), &second);
+        assert!(sink.contains("tls.reloaded"));
+
+        // an invalid key leaves the previous certificate in place
+        fs::write(&files.key, "not a key").unwrap();
+        acceptor.reload("test");
+        assert_served(addr, Some("proxy.test"), &second);
+        assert!(sink.contains("tls.reload_failed"));
+    }
+
+    #[test]
+    fn handshake_failure() {
+        let dir = temp_dir();
+        let (files, cert) = write_certificate(&dir, "proxy.test");
+        let (metrics, sink) = metrics();
+        let acceptor =
+            Arc::new(TlsAcceptor::new(TlsConfig::single(files.cert, files.key), metrics).unwrap());
+        let mut runtime = Runtime::new().unwrap();
+        let addr = listen(acceptor, &mut runtime);
+
+        // a plain HTTP client fails the handshake and gets disconnected
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: proxy.test\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).ok();
+
+        assert!(sent(&sink, "tls.handshake_failed"));
+
+        // the listener keeps accepting connections
+        assert_served(addr, Some("proxy.test"), &cert);
+    }
+}
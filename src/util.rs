@@ -1,44 +1,76 @@
-use log::*;
-
 use hyper::{
     rt::{lazy, Future},
-    Body, Error as HyperError, Method, Response, StatusCode,
+    Body, Error as HyperError, Response, StatusCode,
 };
-use std::fmt::Debug;
-use std::time::Instant;
+use std::sync::Arc;
 
-use geoindex::GeoIndex;
+use geoindex::AreaDefinition;
 
-use crate::config::{Backend, BackendDefinition, ProxyConfig};
+use crate::access::{AccessLog, AccessRecord};
+use crate::config::{BackendDefinition, ProxyConfig};
+use crate::index::AreaIndex;
 use crate::metrics::*;
 
-pub(crate) fn setup_index(config: ProxyConfig) -> GeoIndex<Backend> {
+pub(crate) fn setup_index(config: ProxyConfig) -> AreaIndex {
     let ProxyConfig {
         backends,
+        geojson: _,
         default_backend,
+        fallback,
+        overlap_strategy,
+        location: _,
+        headers: _,
+        retries: _,
+        tls: _,
+        repair_areas: _,
+        coordinate_system,
+        axis_order: _,
+        coordinate_precision,
     } = config;
 
     let defs = backends
         .into_iter()
-        .map(|BackendDefinition { areas, backend }| (areas, backend))
+        .map(
+            |BackendDefinition {
+                 areas,
+                 backend,
+                 priority,
+             }| AreaDefinition {
+                polygons: areas,
+                value: backend,
+                priority,
+            },
+        )
         .collect();
 
-    GeoIndex::new(defs, default_backend)
+    AreaIndex::new(
+        coordinate_precision,
+        defs,
+        default_backend,
+        fallback,
+        overlap_strategy,
+        coordinate_system,
+    )
 }
 
 pub(crate) fn error_result(
     code: StatusCode,
-    method: Method,
-    path: impl Debug,
+    record: AccessRecord,
+    access_log: Arc<AccessLog>,
     metrics: MetricsClient,
-    span: Instant,
-    metric: &'static str,
+    outcome: Outcome,
 ) -> Box<dyn Future<Item = Response<Body>, Error = HyperError> + Send> {
-    let status = format!("{} {} {:?}", code.as_str(), method, path);
-
     Box::new(lazy(move || {
-        error!("{} {:?}", status, span.elapsed());
-        let _ = metrics.incr(metric);
+        let record = record.response(code).error(outcome.name());
+
+        access_log.log(&record);
+        metrics.request(
+            outcome,
+            record.backend_label(),
+            record.method(),
+            code,
+            record.elapsed(),
+        );
 
         Ok(Response::builder()
             .status(code)